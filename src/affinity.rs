@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io;
+
+/// Maps EventProcessor tokens (and the producer) to the CPU cores they may run on
+///
+/// Latency suffers badly when the scheduler moves busy-spinning threads between cores,
+/// or puts two of them on the same core.  Pinning each thread to its own core (or set of
+/// cores) keeps the numbers stable.  Threads without an entry are left to the scheduler.
+///
+/// Pinning is only supported on Linux, where it uses `sched_setaffinity`.
+///
+///# Example
+///
+///```
+///let mut affinity = AffinityConfig::new();
+///affinity.producer(vec![1]).pin(e1, vec![2]).pin(e2, vec![3]);
+///
+///let thread = event_processor.spawn_pinned::<_, BusyWait>(handler, &affinity).unwrap();
+///affinity.pin_producer().unwrap();
+///t.write(Slot::new());
+///```
+#[derive(Clone, Debug)]
+pub struct AffinityConfig {
+    processors: HashMap<usize, Vec<usize>>,
+    producer: Option<Vec<usize>>
+}
+
+impl AffinityConfig {
+    pub fn new() -> AffinityConfig {
+        AffinityConfig {
+            processors: HashMap::new(),
+            producer: None
+        }
+    }
+
+    /// Run the EventProcessor with this token on `cores`
+    ///
+    /// Turbine doesn't spawn WorkerPool workers, so entries for a pool's token are not
+    /// applied automatically.  Workers can pin themselves by calling
+    /// `pin_current_thread()` with `cores_for()` the pool's token before they start.
+    pub fn pin(&mut self, token: usize, cores: Vec<usize>) -> &mut AffinityConfig {
+        self.processors.insert(token, cores);
+        self
+    }
+
+    /// Run the producer thread on `cores`.  See `pin_producer()`.
+    pub fn producer(&mut self, cores: Vec<usize>) -> &mut AffinityConfig {
+        self.producer = Some(cores);
+        self
+    }
+
+    /// The cores configured for `token`, if any
+    pub fn cores_for(&self, token: usize) -> Option<&[usize]> {
+        self.processors.get(&token).map(|cores| &cores[..])
+    }
+
+    /// Pin the calling thread to the producer's cores
+    ///
+    /// Turbine doesn't own the producer thread, so this should be called from whichever
+    /// thread writes into Turbine.  Does nothing if no producer cores were configured.
+    pub fn pin_producer(&self) -> io::Result<()> {
+        match self.producer {
+            Some(ref cores) => pin_current_thread(cores),
+            None => Ok(())
+        }
+    }
+}
+
+/// Restrict the calling thread to `cores`
+///
+/// Fails if `cores` is empty or names a core which doesn't exist, if the OS refuses
+/// the request, or if the platform isn't supported.
+pub fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+    if cores.len() == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no cores given"));
+    }
+    os::pin_current_thread(cores)
+}
+
+#[cfg(target_os = "linux")]
+mod os {
+    use std::io;
+    use std::mem;
+    use libc::{self, cpu_set_t};
+
+    pub fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+        for &core in cores.iter() {
+            if core >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "core number is too large"));
+            }
+        }
+
+        // A pid of 0 means the calling thread
+        let result = unsafe {
+            let mut set: cpu_set_t = mem::zeroed();
+            libc::CPU_ZERO(&mut set);
+            for &core in cores.iter() {
+                libc::CPU_SET(core, &mut set);
+            }
+            libc::sched_setaffinity(0, mem::size_of::<cpu_set_t>(), &set)
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error())
+        }
+    }
+
+    /// The cores the calling thread is currently allowed to run on
+    #[cfg(test)]
+    pub fn allowed_cores() -> io::Result<Vec<usize>> {
+        unsafe {
+            let mut set: cpu_set_t = mem::zeroed();
+            match libc::sched_getaffinity(0, mem::size_of::<cpu_set_t>(), &mut set) {
+                0 => Ok((0..libc::CPU_SETSIZE as usize).filter(|&core| libc::CPU_ISSET(core, &set)).collect()),
+                _ => Err(io::Error::last_os_error())
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod os {
+    use std::io;
+
+    pub fn pin_current_thread(_: &[usize]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "CPU pinning is only supported on Linux"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{AffinityConfig, pin_current_thread};
+    use std::thread;
+
+    #[test]
+    fn config_lookup() {
+        let mut affinity = AffinityConfig::new();
+        affinity.pin(0, vec![1, 2]).pin(3, vec![4]);
+
+        assert!(affinity.cores_for(0) == Some(&[1, 2][..]));
+        assert!(affinity.cores_for(3) == Some(&[4][..]));
+        assert!(affinity.cores_for(1) == None);
+        assert!(affinity.pin_producer().is_ok());
+    }
+
+    #[test]
+    fn invalid_cores() {
+        assert!(pin_current_thread(&[]).is_err());
+        assert!(pin_current_thread(&[1 << 20]).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pin_to_allowed_core() {
+        // Containers (or taskset) may not allow every core, so use one we may already run on
+        let (core, allowed) = thread::spawn(|| {
+            let core = super::os::allowed_cores().unwrap()[0];
+            pin_current_thread(&[core]).unwrap();
+            (core, super::os::allowed_cores().unwrap())
+        }).join().unwrap();
+        assert!(allowed == vec![core]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering::SeqCst;
+use std::time::{Duration, Instant};
+use atomicnum::AtomicNum;
+use paddedatomics::Padded64;
+use subscriber::DynamicCursors;
+
+/// Stored in `Backpressure::bottleneck` when a Subscriber was the slowest consumer
+const SUBSCRIBER: u64 = 18446744073709551615;
+
+/// The consumer holding back a producer which is waiting for a free slot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bottleneck {
+    /// The EventProcessor (or WorkerPool) with this token
+    Processor(usize),
+
+    /// A Subscriber attached with `Turbine::subscribe()`
+    Subscriber
+}
+
+/// Details of a producer stall, passed to the callback set with `Turbine::on_stall()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stall {
+    /// The sequence the producer is waiting to write
+    pub sequence: u64,
+
+    /// The slowest consumer when the producer started waiting
+    pub bottleneck: Option<Bottleneck>,
+
+    /// How long the producer has been waiting so far
+    pub duration: Duration
+}
+
+/// Producer statistics, shared by the Turbine, its MultiProducers and StatsHandles
+///
+/// The counters are only touched when a write has to wait for a free slot, so they
+/// cost nothing while the ring has room.
+pub struct Backpressure {
+    waited_writes: AtomicNum<u64>,
+    spin_iterations: AtomicNum<u64>,
+    wait_ns: AtomicNum<u64>,
+    bottleneck: AtomicNum<u64>,    // token + 1, SUBSCRIBER, or 0 if the producer has never waited
+    stall: Option<(Duration, Box<Fn(&Stall) + Send + Sync>)>
+}
+
+impl Backpressure {
+    pub fn new() -> Backpressure {
+        Backpressure {
+            waited_writes: AtomicNum::new(0),
+            spin_iterations: AtomicNum::new(0),
+            wait_ns: AtomicNum::new(0),
+            bottleneck: AtomicNum::new(0),
+            stall: None
+        }
+    }
+
+    /// Call `callback` whenever a single write has waited longer than `threshold`
+    pub fn set_stall_callback(&mut self, threshold: Duration, callback: Box<Fn(&Stall) + Send + Sync>) {
+        self.stall = Some((threshold, callback));
+    }
+
+    /// The number of writes which had to wait for a free slot
+    pub fn waited_writes(&self) -> u64 {
+        self.waited_writes.load(SeqCst)
+    }
+
+    /// The number of times waiting writes checked for a free slot
+    pub fn spin_iterations(&self) -> u64 {
+        self.spin_iterations.load(SeqCst)
+    }
+
+    /// The total time writes spent waiting for a free slot
+    pub fn wait_ns(&self) -> u64 {
+        self.wait_ns.load(SeqCst)
+    }
+
+    /// The slowest consumer the last time a write had to wait
+    pub fn bottleneck(&self) -> Option<Bottleneck> {
+        match self.bottleneck.load(SeqCst) {
+            0 => None,
+            SUBSCRIBER => Some(Bottleneck::Subscriber),
+            token => Some(Bottleneck::Processor(token as usize - 1))
+        }
+    }
+}
+
+/// Find the consumer with the lowest cursor, EP or Subscriber
+///
+/// `cursors` is the full cursor vector; the root cursor at index 0 is skipped.
+fn slowest(cursors: &[Padded64], dynamic: &DynamicCursors) -> Option<Bottleneck> {
+    let mut slowest = None;
+    let mut min_cursor = 18446744073709551615;
+    for (token, v) in cursors.iter().skip(1).enumerate() {
+        let cursor = v.load();
+        if cursor < min_cursor {
+            min_cursor = cursor;
+            slowest = Some(Bottleneck::Processor(token));
+        }
+    }
+
+    match dynamic.minimum(min_cursor, None) < min_cursor {
+        true => Some(Bottleneck::Subscriber),
+        false => slowest
+    }
+}
+
+/// Tracks a single write which is waiting for a free slot
+///
+/// Created once the first check fails, `spin()` is called after every further failed
+/// check, and the statistics are recorded when it is dropped.
+pub struct Waiting {
+    backpressure: Arc<Backpressure>,
+    sequence: u64,
+    bottleneck: Option<Bottleneck>,
+    start: Instant,
+    spins: u64,
+    reported: bool
+}
+
+impl Waiting {
+    pub fn new(backpressure: Arc<Backpressure>, sequence: u64, cursors: &[Padded64],
+               dynamic: &DynamicCursors) -> Waiting {
+        Waiting {
+            backpressure: backpressure,
+            sequence: sequence,
+            bottleneck: slowest(cursors, dynamic),
+            start: Instant::now(),
+            spins: 1,
+            reported: false
+        }
+    }
+
+    /// Record another failed check, reporting a stall if it has gone on for too long
+    #[inline]
+    pub fn spin(&mut self) {
+        self.spins += 1;
+
+        if self.reported == false {
+            if let Some((threshold, ref callback)) = self.backpressure.stall {
+                let duration = self.start.elapsed();
+                if duration >= threshold {
+                    self.reported = true;
+                    callback(&Stall {
+                        sequence: self.sequence,
+                        bottleneck: self.bottleneck,
+                        duration: duration
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let ns = elapsed.as_secs() * 1000000000 + elapsed.subsec_nanos() as u64;
+
+        self.backpressure.waited_writes.fetch_add(1, SeqCst);
+        self.backpressure.spin_iterations.fetch_add(self.spins, SeqCst);
+        self.backpressure.wait_ns.fetch_add(ns, SeqCst);
+        match self.bottleneck {
+            Some(Bottleneck::Processor(token)) => self.backpressure.bottleneck.store(token as u64 + 1, SeqCst),
+            Some(Bottleneck::Subscriber) => self.backpressure.bottleneck.store(SUBSCRIBER, SeqCst),
+            None => {}
+        }
+    }
+}
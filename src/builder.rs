@@ -0,0 +1,199 @@
+use ringbuffer::Slot;
+use waitstrategy::WaitStrategy;
+use handler::EventHandler;
+use halt::HaltHandle;
+use spawn::{self, ProcessorThread, BoxedExceptionHandler};
+use eventprocessor::EventProcessor;
+use exception::ExceptionHandler;
+use affinity::AffinityConfig;
+use error::TurbineError;
+use Turbine;
+
+/// A boxed EventHandler, so handlers of different types can share a stage
+pub type BoxedHandler<T, E> = Box<EventHandler<T, Error=E> + Send>;
+
+/// Builds a pipeline of EventHandlers, one stage at a time
+///
+/// Each stage holds one or more handlers which run side by side, and every handler of
+/// a stage depends on every handler of the previous stage.  This wires the common
+/// topologies without juggling EP tokens:
+///
+///```
+///// journal --> logic
+///turbine.handle_with(journal).then(logic)
+///
+///// journal, replicate --> logic   (a diamond, once the writer is counted)
+///turbine.handle_with_all(vec![journal, replicate]).then(logic)
+///
+///// handlers of different types can share a stage with and()
+///turbine.handle_with(journal).and(replicate).then(logic)
+///```
+///
+/// All handlers of a pipeline share the same error type.  Nothing is created until
+/// `start()` is called.  Obtained from `Turbine::handle_with()` or
+/// `Turbine::handle_with_all()`.
+pub struct PipelineBuilder<'a, T: 'a, E> {
+    turbine: &'a mut Turbine<T>,
+    stages: Vec<Vec<BoxedHandler<T, E>>>,
+    affinity: AffinityConfig,
+    exceptions: Option<Box<Fn() -> BoxedExceptionHandler<T>>>
+}
+
+impl<'a, T: Slot + 'static, E: Send + 'static> PipelineBuilder<'a, T, E> {
+
+    /// Instantiate a new PipelineBuilder.  This is for internal use only.
+    pub fn new(turbine: &'a mut Turbine<T>, first: Vec<BoxedHandler<T, E>>) -> PipelineBuilder<'a, T, E> {
+        PipelineBuilder {
+            turbine: turbine,
+            stages: vec![first],
+            affinity: AffinityConfig::new(),
+            exceptions: None
+        }
+    }
+
+    /// Add `handler` to the current stage, running alongside the stage's other handlers
+    pub fn and<H>(mut self, handler: H) -> PipelineBuilder<'a, T, E>
+    where H: EventHandler<T, Error=E> + Send + 'static {
+        self.stages.last_mut().unwrap().push(Box::new(handler));
+        self
+    }
+
+    /// Start a new stage with `handler`, which depends on every handler of the current stage
+    pub fn then<H>(mut self, handler: H) -> PipelineBuilder<'a, T, E>
+    where H: EventHandler<T, Error=E> + Send + 'static {
+        self.stages.push(vec![Box::new(handler)]);
+        self
+    }
+
+    /// Start a new stage with several handlers, each of which depends on every handler
+    /// of the current stage
+    pub fn then_all<H>(mut self, handlers: Vec<H>) -> PipelineBuilder<'a, T, E>
+    where H: EventHandler<T, Error=E> + Send + 'static {
+        self.stages.push(boxed(handlers));
+        self
+    }
+
+    /// Pin the pipeline's threads according to `affinity`
+    ///
+    /// Handlers are given EP tokens in the order they were added, starting after any EPs
+    /// created by hand, so the first handler of a fresh Turbine has token 0.
+    pub fn with_affinity(mut self, affinity: AffinityConfig) -> PipelineBuilder<'a, T, E> {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Pass panics in every handler of the pipeline to a copy of `exceptions`, rather than halting
+    ///
+    /// See `EventProcessor::start_with_exception_handler()`.
+    pub fn with_exception_handler<X>(mut self, exceptions: X) -> PipelineBuilder<'a, T, E>
+    where X: ExceptionHandler<T> + Clone + Send + 'static {
+        self.exceptions = Some(Box::new(move|| Box::new(exceptions.clone()) as BoxedExceptionHandler<T>));
+        self
+    }
+
+    /// Wire the dependency graph, finalize it and spawn one thread per handler
+    ///
+    /// Handlers are driven by `EventProcessor::spawn()` using the wait strategy `W`, so
+    /// each thread is named after its EP's token.  This finalizes the Turbine's graph, so it fails with
+    /// `TurbineError::AlreadyFinalized` if the graph has already been finalized.  EPs
+    /// added by hand with `ep_new()` beforehand are finalized too, and must be started
+    /// by hand.  If the OS refuses to create one of the threads, the Turbine is halted
+    /// so the threads already spawned drain and exit, and `TurbineError::ThreadSpawn`
+    /// is returned.
+    pub fn start<W: WaitStrategy + 'static>(self) -> Result<Pipeline<T, E>, TurbineError> {
+        let turbine = self.turbine;
+
+        let mut tokens: Vec<Vec<usize>> = Vec::with_capacity(self.stages.len());
+        for (i, stage) in self.stages.iter().enumerate() {
+            let mut stage_tokens = Vec::with_capacity(stage.len());
+            for _ in stage.iter() {
+                let token = try!(turbine.ep_new());
+                if i > 0 {
+                    for &dep in tokens[i - 1].iter() {
+                        try!(turbine.ep_depends(token, dep));
+                    }
+                }
+                stage_tokens.push(token);
+            }
+            tokens.push(stage_tokens);
+        }
+
+        let mut processors = Vec::with_capacity(tokens.len());
+        for &token in tokens.iter().flat_map(|t| t.iter()) {
+            processors.push(try!(turbine.ep_finalize(token)));
+        }
+
+        let handlers = self.stages.into_iter().flat_map(|s| s.into_iter());
+        spawn_all::<T, E, W>(turbine, processors.into_iter().zip(handlers).collect(), &self.affinity, &self.exceptions)
+    }
+}
+
+/// Spawn a thread for each EP and its handler, see `EventProcessor::spawn()`
+///
+/// If the OS refuses to create one of the threads, the Turbine is halted so the threads
+/// already spawned drain and exit, and `TurbineError::ThreadSpawn` is returned.
+pub fn spawn_all<T, E, W>(turbine: &Turbine<T>, processors: Vec<(EventProcessor<T>, BoxedHandler<T, E>)>,
+                          affinity: &AffinityConfig, exceptions: &Option<Box<Fn() -> BoxedExceptionHandler<T>>>)
+                          -> Result<Pipeline<T, E>, TurbineError>
+where T: Slot + 'static, E: Send + 'static, W: WaitStrategy + 'static {
+    let mut threads = Vec::with_capacity(processors.len());
+    for (ep, handler) in processors.into_iter() {
+        let cores = affinity.cores_for(ep.token()).map(|c| c.to_vec());
+        match spawn::spawn::<T, _, W>(ep, handler, cores, exceptions.as_ref().map(|x| x())) {
+            Ok(thread) => threads.push(thread),
+            Err(e) => {
+                turbine.halt();
+                return Err(TurbineError::ThreadSpawn(e.kind()));
+            }
+        }
+    }
+
+    Ok(Pipeline {
+        threads: threads,
+        halt: turbine.halt_handle()
+    })
+}
+
+/// Box each of `handlers`
+pub fn boxed<T, H>(handlers: Vec<H>) -> Vec<BoxedHandler<T, H::Error>>
+where H: EventHandler<T> + Send + 'static {
+    handlers.into_iter().map(|h| Box::new(h) as BoxedHandler<T, H::Error>).collect()
+}
+
+/// A handle to a running pipeline, returned by `PipelineBuilder::start()` and `Turbine::start()`
+///
+/// The pipeline owns the threads it started, and is the only way to join them.
+pub struct Pipeline<T, E> {
+    threads: Vec<ProcessorThread<T, BoxedHandler<T, E>>>,
+    halt: HaltHandle
+}
+
+impl<T, E> Pipeline<T, E> {
+
+    /// The names of the pipeline's threads, in the order the handlers were added
+    pub fn thread_names(&self) -> Vec<&str> {
+        self.threads.iter().map(|t| t.name()).collect()
+    }
+
+    /// Halt the Turbine which this pipeline reads from.  See `Turbine::halt()`.
+    pub fn halt(&self) {
+        self.halt.halt();
+    }
+
+    /// Wait for every handler's thread to finish
+    ///
+    /// Returns each handler and its EP's final cursor, in the order the handlers were
+    /// added to the builder, or the error which stopped it.  Handlers only stop by
+    /// themselves when they return an error, so this is usually preceded by a `halt()`.
+    /// Panics if a handler panicked.
+    pub fn join(self) -> Vec<Result<(BoxedHandler<T, E>, u64), E>> {
+        self.threads.into_iter().map(|t| t.join()).collect()
+    }
+
+    /// Halt the Turbine, wait for every handler to drain what has already been
+    /// published, and return the handlers.  See `join()`.
+    pub fn shutdown(self) -> Vec<Result<(BoxedHandler<T, E>, u64), E>> {
+        self.halt();
+        self.join()
+    }
+}
@@ -0,0 +1,223 @@
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+use std::thread;
+use paddedatomics::Padded64;
+use sequencer::AvailableBuffer;
+use waitstrategy::WaitSignal;
+use latency::Latency;
+
+/// How a Claim makes its slot visible to EventProcessors
+pub enum Publisher<'a> {
+    /// Single producer: advance the root cursor past the claimed sequence
+    Cursor(&'a Padded64),
+
+    /// Multiple producers: flag the claimed sequence in the availability buffer
+    Available(&'a AvailableBuffer)
+}
+
+impl<'a> Publisher<'a> {
+    /// Publish the sequences `[from, to)`
+    fn publish(&self, from: u64, to: u64) {
+        match *self {
+            Publisher::Cursor(cursor) => cursor.store(to),
+            Publisher::Available(available) => {
+                for sequence in from..to {
+                    available.set(sequence);
+                }
+            }
+        }
+    }
+}
+
+/// Exclusive, in-place access to a single slot of the ring buffer
+///
+/// A Claim is returned by `Turbine::claim()` and `MultiProducer::claim()`.  It
+/// dereferences to the slot itself, so data can be written directly into the
+/// preallocated buffer instead of being moved in.  Note that the slot still holds
+/// whatever was written there one lap ago, so every field you care about must be
+/// overwritten.
+///
+/// The slot is published when the Claim is dropped, or explicitly with `publish()`.
+///
+/// This includes a Claim dropped while the producer is unwinding from a panic, in which
+/// case consumers see whatever had been written into the slot so far.  The slot is
+/// published regardless because its sequence has already been taken: leaving it
+/// unpublished would stop every EventProcessor at that sequence for good.  A warning
+/// is logged when this happens.
+///
+///# Example
+///
+///```
+///let mut t: Turbine<TestSlot> = Turbine::new(1024);
+///let e1 = t.ep_new().unwrap();
+///let event_processor = t.ep_finalize(e1).unwrap();
+///
+///{
+///    let mut slot = t.claim();
+///    slot.value = 19;
+///}   // published here
+///
+///let mut slot = t.claim();
+///slot.value = 20;
+///slot.publish();
+///```
+pub struct Claim<'a, T: 'a> {
+    slot: &'a mut T,
+    sequence: u64,
+    publisher: Publisher<'a>,
+    signal: &'a WaitSignal,
+    latency: Option<&'a Latency>
+}
+
+impl<'a, T> Claim<'a, T> {
+    /// Instantiate a new Claim.  This is for internal use only.
+    pub fn new(slot: &'a mut T, sequence: u64, publisher: Publisher<'a>, signal: &'a WaitSignal,
+               latency: Option<&'a Latency>) -> Claim<'a, T> {
+        Claim {
+            slot: slot,
+            sequence: sequence,
+            publisher: publisher,
+            signal: signal,
+            latency: latency
+        }
+    }
+
+    /// The sequence number which was claimed
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Publish the slot, making it visible to EventProcessors
+    ///
+    /// This is equivalent to dropping the Claim, but makes the intent explicit.
+    pub fn publish(self) {}
+}
+
+impl<'a, T> Deref for Claim<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.slot
+    }
+}
+
+impl<'a, T> DerefMut for Claim<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.slot
+    }
+}
+
+impl<'a, T> Drop for Claim<'a, T> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            warn!("Publishing sequence {} while panicking, it may be partially written", self.sequence);
+        }
+        if let Some(latency) = self.latency {
+            latency.stamp(self.sequence, self.sequence + 1);
+        }
+        self.publisher.publish(self.sequence, self.sequence + 1);
+        self.signal.notify();
+        debug!("Published {}", self.sequence);
+    }
+}
+
+/// Exclusive, in-place access to several contiguous slots of the ring buffer
+///
+/// A BatchClaim is returned by `Turbine::claim_batch()` and `MultiProducer::claim_batch()`.
+/// The claimed sequences may wrap around the end of the ring, so the slots are exposed
+/// as two slices (the second is empty if there was no wraparound).  The BatchClaim may
+/// also be indexed directly, where index 0 is the first claimed sequence.
+///
+/// All slots are published at once when the BatchClaim is dropped, or explicitly with
+/// `publish()`.  Like a Claim, they are also published if the producer panics while
+/// holding the BatchClaim.
+///
+///# Example
+///
+///```
+///let mut batch = t.claim_batch(40);
+///for i in 0..batch.len() {
+///    batch[i].value = i;
+///}
+///batch.publish();
+///```
+pub struct BatchClaim<'a, T: 'a> {
+    first: &'a mut [T],
+    second: &'a mut [T],
+    sequence: u64,
+    publisher: Publisher<'a>,
+    signal: &'a WaitSignal,
+    latency: Option<&'a Latency>
+}
+
+impl<'a, T> BatchClaim<'a, T> {
+    /// Instantiate a new BatchClaim.  This is for internal use only.
+    pub fn new(first: &'a mut [T], second: &'a mut [T], sequence: u64, publisher: Publisher<'a>,
+               signal: &'a WaitSignal, latency: Option<&'a Latency>) -> BatchClaim<'a, T> {
+        BatchClaim {
+            first: first,
+            second: second,
+            sequence: sequence,
+            publisher: publisher,
+            signal: signal,
+            latency: latency
+        }
+    }
+
+    /// The first sequence number which was claimed
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// The number of slots which were claimed
+    pub fn len(&self) -> usize {
+        self.first.len() + self.second.len()
+    }
+
+    /// The claimed slots, split at the end of the ring
+    pub fn as_mut_slices(&mut self) -> (&mut [T], &mut [T]) {
+        (&mut *self.first, &mut *self.second)
+    }
+
+    /// Publish all the claimed slots, making them visible to EventProcessors
+    ///
+    /// This is equivalent to dropping the BatchClaim, but makes the intent explicit.
+    pub fn publish(self) {}
+}
+
+impl<'a, T> Index<usize> for BatchClaim<'a, T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        match index < self.first.len() {
+            true => &self.first[index],
+            false => &self.second[index - self.first.len()]
+        }
+    }
+}
+
+impl<'a, T> IndexMut<usize> for BatchClaim<'a, T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        match index < self.first.len() {
+            true => &mut self.first[index],
+            false => {
+                let offset = self.first.len();
+                &mut self.second[index - offset]
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for BatchClaim<'a, T> {
+    fn drop(&mut self) {
+        let end = self.sequence + self.len() as u64;
+        if thread::panicking() {
+            warn!("Publishing sequences {} to {} while panicking, they may be partially written", self.sequence, end);
+        }
+        if let Some(latency) = self.latency {
+            latency.stamp(self.sequence, end);
+        }
+        self.publisher.publish(self.sequence, end);
+        self.signal.notify();
+        debug!("Published {} to {}", self.sequence, end);
+    }
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use graph::GraphError;
+
+/// Errors returned by the Turbine API
+#[derive(Debug, Clone, PartialEq)]
+pub enum TurbineError {
+    /// The dependency graph has been finalized, so it can no longer be modified
+    AlreadyFinalized,
+
+    /// The dependency graph has not been finalized yet
+    NotFinalized,
+
+    /// The operation requires a Turbine created with `Turbine::new_multi_producer()`
+    NotMultiProducer,
+
+    /// The token does not belong to any EventProcessor
+    UnknownProcessor(usize),
+
+    /// The token belongs to a WorkerPool, which must be finalized with `pool_finalize()`
+    WorkerPoolToken(usize),
+
+    /// The token belongs to an EventProcessor rather than a WorkerPool
+    NotWorkerPool(usize),
+
+    /// The EventProcessor (or WorkerPool) for this token has already been handed out
+    AlreadyTaken(usize),
+
+    /// The dependency graph is invalid and cannot be finalized
+    InvalidDependency(GraphError),
+
+    /// There is no free slot in the ring buffer
+    RingFull,
+
+    /// The Turbine has been halted
+    Halted,
+
+    /// The sequence has already been released to the producer, or not written yet
+    SequenceUnavailable(u64),
+
+    /// The OS refused to create a thread for an EventProcessor
+    ThreadSpawn(io::ErrorKind)
+}
+
+impl From<GraphError> for TurbineError {
+    fn from(err: GraphError) -> TurbineError {
+        TurbineError::InvalidDependency(err)
+    }
+}
+
+impl fmt::Display for TurbineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TurbineError::UnknownProcessor(token) => write!(f, "unknown EventProcessor {}", token),
+            TurbineError::WorkerPoolToken(token) => write!(f, "EventProcessor {} is a WorkerPool", token),
+            TurbineError::NotWorkerPool(token) => write!(f, "EventProcessor {} is not a WorkerPool", token),
+            TurbineError::AlreadyTaken(token) => write!(f, "EventProcessor {} has already been finalized", token),
+            TurbineError::InvalidDependency(ref err) => write!(f, "invalid dependency graph: {}", err),
+            TurbineError::SequenceUnavailable(sequence) => write!(f, "sequence {} is not available", sequence),
+            TurbineError::ThreadSpawn(kind) => write!(f, "could not spawn an EventProcessor thread: {:?}", kind),
+            _ => write!(f, "{}", self.description())
+        }
+    }
+}
+
+impl Error for TurbineError {
+    fn description(&self) -> &str {
+        match *self {
+            TurbineError::AlreadyFinalized => "the dependency graph has already been finalized",
+            TurbineError::NotFinalized => "the dependency graph has not been finalized",
+            TurbineError::NotMultiProducer => "the Turbine was not created in multi-producer mode",
+            TurbineError::UnknownProcessor(_) => "unknown EventProcessor",
+            TurbineError::WorkerPoolToken(_) => "the token belongs to a WorkerPool",
+            TurbineError::NotWorkerPool(_) => "the token does not belong to a WorkerPool",
+            TurbineError::AlreadyTaken(_) => "the EventProcessor has already been finalized",
+            TurbineError::InvalidDependency(_) => "invalid dependency graph",
+            TurbineError::RingFull => "the ring buffer is full",
+            TurbineError::Halted => "the Turbine has been halted",
+            TurbineError::SequenceUnavailable(_) => "the sequence is not available",
+            TurbineError::ThreadSpawn(_) => "could not spawn an EventProcessor thread"
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            TurbineError::InvalidDependency(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
@@ -1,16 +1,33 @@
 
 
+use std::io;
 use std::sync::Arc;
-use waitstrategy::WaitStrategy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::thread;
+use futures::{Stream, Poll, Async};
+use waitstrategy::{WaitStrategy, WaitSignal};
 use paddedatomics::Padded64;
 use ringbuffer::{RingBuffer, Slot};
+use sequencer::{self, AvailableBuffer};
+use handler::EventHandler;
+use spawn::{self, ProcessorThread};
+use affinity::AffinityConfig;
+use exception::{self, ExceptionHandler};
+use latency::Latency;
+use poller::EventPoller;
 
 /// EventProcessors provide functionality to process and consume data from the ring buffer
 pub struct EventProcessor<T> {
     graph: Arc<Vec<Vec<usize>>>,
     cursors: Arc<Vec<Padded64>>,
+    stopped: Arc<Vec<AtomicBool>>,
     token: usize,
-    ring: Arc<RingBuffer<T>>
+    mutable: bool,
+    ring: Arc<RingBuffer<T>>,
+    available: Option<Arc<AvailableBuffer>>,
+    signal: Arc<WaitSignal>,
+    latency: Option<Arc<Latency>>
 }
 
 
@@ -22,13 +39,26 @@ impl<T: Slot> EventProcessor<T> {
     /// - ring: an instance of the ring buffer
     /// - graph: a dependency graph, showing how all the EPs relate to eachother.
     /// - cursors: a vector of Padded64 atomics which act as cursors into the ring buffer
+    /// - stopped: a flag per cursor, set once the owning EP has stopped processing
+    /// - available: per-slot publication markers, only present in multi-producer mode
+    /// - signal: the signal used to wake blocked EventProcessors
     /// - token: the index in the graph which represents this EP
-    pub fn new(ring: Arc<RingBuffer<T>>, graph: Arc<Vec<Vec<usize>>>, cursors: Arc<Vec<Padded64>>, token: usize) -> EventProcessor<T> {
+    /// - mutable: whether the graph allows this EP to modify events (see `Turbine::ep_mutable()`)
+    /// - latency: publish timestamps and histograms, only present if latency is tracked
+    pub fn new(ring: Arc<RingBuffer<T>>, graph: Arc<Vec<Vec<usize>>>, cursors: Arc<Vec<Padded64>>,
+               stopped: Arc<Vec<AtomicBool>>, available: Option<Arc<AvailableBuffer>>,
+               signal: Arc<WaitSignal>, token: usize, mutable: bool,
+               latency: Option<Arc<Latency>>) -> EventProcessor<T> {
         EventProcessor::<T> {
             graph: graph,
             cursors: cursors,
+            stopped: stopped,
             token: token,
-            ring: ring
+            mutable: mutable,
+            ring: ring,
+            available: available,
+            signal: signal,
+            latency: latency
         }
     }
 
@@ -43,25 +73,247 @@ impl<T: Slot> EventProcessor<T> {
     /// The user-code running inside the closure must be capable of handling multiple pieces of data.
     ///
     /// Upon completion of processing the data, the closure must return a Result signaling if it wants the event processor
-    /// to continue running, or exit.  A Result of Ok(()) will tell the EP to continue running.  A Result of Err(()) will
-    /// shut down the EP.
+    /// to continue running, or exit.  A Result of Ok(()) will tell the EP to continue running.  A Result of Err(e) will
+    /// shut down the EP, and `start()` returns that same Err(e).  The error type is chosen by the closure, so the reason
+    /// the EP stopped can be passed back to whoever is waiting on it.
+    ///
+    /// The EP also shuts down once the Turbine has been halted and everything published before the halt has been
+    /// processed.  In that case `start()` returns Ok with the EP's final cursor position.
+    ///
+    /// If the closure panics, the panic is caught and handled like `HaltOnException`: it is logged, the batch is
+    /// skipped and the Turbine is halted, so the pipeline drains and stops instead of wedging the writer.  The same
+    /// goes for the other `start` methods; `start_with_exception_handler()` lets the EP choose what happens instead.
     ///
     /// ## Example
     ///
     ///```
-    ///spawn(proc() {
-    ///     event_processor.start::<BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+    ///thread::spawn(move|| {
+    ///     let result = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(), MyError> {
     ///         assert!(data.len() == 1);
     ///         assert!(data[0].value == 19);
     ///         return Ok(());
     ///     });
     ///});
     ///```
-    pub fn start<F, W: WaitStrategy>(&self, mut f: F)
-    where F: FnMut(&[T]) -> Result<(),()> {
+    pub fn start<F, E, W: WaitStrategy>(&self, mut f: F) -> Result<u64, E>
+    where F: FnMut(&[T]) -> Result<(), E> {
+        self.run::<_, _, E, W>(None, |offset, len, sequence, _| {
+            exception::guard(|| f(unsafe { self.ring.get(offset, len) }), &mut None, &self.signal, &self.ring, offset, sequence)
+                .map_err(|e| (sequence + len as u64, e))
+        }, |_| Ok(()))
+    }
+
+    /// Begin waiting for data to arrive, with mutable access to the events.
+    ///
+    /// This behaves exactly like `start()`, but the closure receives a `&mut [T]` so the
+    /// EP can enrich events in place before downstream EPs see them.  The EP must have
+    /// been marked with `Turbine::ep_mutable()` before the graph was finalized, which
+    /// guarantees no other EP reads these slots until this EP's cursor has passed them.
+    ///
+    /// Panics if the EP was not marked as mutable.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///thread::spawn(move|| {
+    ///     event_processor.start_mut::<_, _, BusyWait>(|data: &mut [TestSlot]| -> Result<(), ()> {
+    ///         for event in data.iter_mut() {
+    ///             event.value *= 2;
+    ///         }
+    ///         Ok(())
+    ///     });
+    ///});
+    ///```
+    pub fn start_mut<F, E, W: WaitStrategy>(&self, mut f: F) -> Result<u64, E>
+    where F: FnMut(&mut [T]) -> Result<(), E> {
+        assert!(self.mutable, "EventProcessor {} was not marked with ep_mutable()", self.token);
+
+        // Exclusive access is guaranteed by the graph: every other EP is either behind
+        // one of our dependencies or waiting on our cursor
+        self.run::<_, _, E, W>(None, |offset, len, sequence, _| {
+            exception::guard(|| f(unsafe { self.ring.get_range_mut(offset, len) }), &mut None, &self.signal, &self.ring, offset, sequence)
+                .map_err(|e| (sequence + len as u64, e))
+        }, |_| Ok(()))
+    }
+
+    /// Begin waiting for data to arrive, passing each event to an EventHandler.
+    ///
+    /// This drives the same batching loop as `start()`, but instead of handing out slices
+    /// it calls `handler.on_event()` once per event, with the event's sequence number and
+    /// a flag marking the last event of the batch.  `on_start()` is called before waiting
+    /// for the first event and `on_shutdown()` after the EP stops, whatever the reason.
+    ///
+    /// If `on_event()` returns Err(e), the EP shuts down and `start_with_handler()` returns
+    /// that Err(e).  The cursor stops just past the failing event, so the rest of the batch
+    /// never reaches downstream EPs.  After a halt, it returns Ok with the EP's final cursor position.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///thread::spawn(move|| {
+    ///     let mut journaller = Journaller::new();
+    ///     let result = event_processor.start_with_handler::<_, BusyWait>(&mut journaller);
+    ///});
+    ///```
+    pub fn start_with_handler<H, W: WaitStrategy>(&self, handler: &mut H) -> Result<u64, H::Error>
+    where H: EventHandler<T> + ?Sized {
+        self.run_handler::<H, W>(handler, None)
+    }
+
+    /// Begin waiting for data to arrive, passing each event to an EventHandler and
+    /// catching any panics.
+    ///
+    /// This behaves like `start_with_handler()`, but if `on_event()` panics,
+    /// `exceptions.on_exception()` is called with the panic, the event and its sequence,
+    /// and decides whether the EP carries on or halts the Turbine.  In both cases the
+    /// event is skipped, so a single bad event cannot stop the EP's cursor and wedge
+    /// the writer.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///thread::spawn(move|| {
+    ///     let mut journaller = Journaller::new();
+    ///     let result = event_processor.start_with_exception_handler::<_, _, BusyWait>(&mut journaller,
+    ///                                                                                  &mut LogAndContinue);
+    ///});
+    ///```
+    pub fn start_with_exception_handler<H, X, W: WaitStrategy>(&self, handler: &mut H, exceptions: &mut X) -> Result<u64, H::Error>
+    where H: EventHandler<T> + ?Sized, X: ExceptionHandler<T> {
+        self.run_handler::<H, W>(handler, Some(exceptions))
+    }
+
+    /// Move this EP into a new thread which drives `handler`.
+    ///
+    /// The thread is named `turbine-ep-<token>` and runs `start_with_handler()` with the
+    /// wait strategy `W`.  The returned ProcessorThread hands the handler and the final
+    /// cursor back (or the error which stopped it) once the EP has stopped, e.g. after
+    /// `Turbine::halt()`.  Fails if the OS refuses to create the thread.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///let thread = event_processor.spawn::<_, BlockingWait>(Journaller::new()).unwrap();
+    ///
+    ///t.write(Slot::new());
+    ///t.halt();
+    ///let (journaller, cursor) = thread.join().unwrap();
+    ///```
+    pub fn spawn<H, W>(self, handler: H) -> io::Result<ProcessorThread<T, H>>
+    where T: 'static, H: EventHandler<T> + Send + 'static, H::Error: Send + 'static,
+          W: WaitStrategy + 'static {
+        spawn::spawn::<T, H, W>(self, handler, None, None)
+    }
+
+    /// Like `spawn()`, but the thread is pinned to the cores `affinity` maps this EP's
+    /// token to (if any).  See `AffinityConfig`.
+    pub fn spawn_pinned<H, W>(self, handler: H, affinity: &AffinityConfig) -> io::Result<ProcessorThread<T, H>>
+    where T: 'static, H: EventHandler<T> + Send + 'static, H::Error: Send + 'static,
+          W: WaitStrategy + 'static {
+        let cores = affinity.cores_for(self.token).map(|c| c.to_vec());
+        spawn::spawn::<T, H, W>(self, handler, cores, None)
+    }
+
+    /// The token of this EP, as returned by `Turbine::ep_new()`
+    pub fn token(&self) -> usize {
+        self.token
+    }
+
+    /// Turn this EP into an EventPoller, which handles events on demand rather than in a loop.
+    ///
+    /// See `EventPoller`.  The poller only hands out shared references, so it does not
+    /// matter whether the EP was marked with `ep_mutable()`.
+    pub fn poller(self) -> EventPoller<T> {
+        EventPoller::new(self.ring, self.graph, self.cursors, self.stopped, self.available,
+                         self.signal, self.token, self.latency)
+    }
+
+    /// Begin waiting for data to arrive, invoking `on_timeout` if the ring stays idle.
+    ///
+    /// This behaves exactly like `start()`, but if no data arrives within `timeout` the
+    /// `on_timeout` closure is called with the EP's current cursor position.  This gives
+    /// the EP a chance to flush buffers, emit heartbeats, etc. on an idle pipeline.
+    ///
+    /// Like the data closure, `on_timeout` returns a Result.  Ok(()) resumes waiting for
+    /// data (and the timeout starts again), while Err(e) shuts down the EP and is returned
+    /// from `start_with_timeout()`.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///thread::spawn(move|| {
+    ///     event_processor.start_with_timeout::<_, _, _, BlockingWait>(Duration::from_millis(100),
+    ///         |data: &[TestSlot]| -> Result<(),()> {
+    ///             // ... process work here ... //
+    ///             Ok(())
+    ///         },
+    ///         |cursor: u64| -> Result<(),()> {
+    ///             // ... flush, heartbeat, or Err(()) to stop ... //
+    ///             Ok(())
+    ///         });
+    ///});
+    ///```
+    pub fn start_with_timeout<F, H, E, W: WaitStrategy>(&self, timeout: Duration, mut f: F, on_timeout: H) -> Result<u64, E>
+    where F: FnMut(&[T]) -> Result<(), E>, H: FnMut(u64) -> Result<(), E> {
+        self.run::<_, H, E, W>(Some(timeout), |offset, len, sequence, _| {
+            exception::guard(|| f(unsafe { self.ring.get(offset, len) }), &mut None, &self.signal, &self.ring, offset, sequence)
+                .map_err(|e| (sequence + len as u64, e))
+        }, on_timeout)
+    }
+
+    /// The loop behind `start_with_handler()` and `start_with_exception_handler()`
+    ///
+    /// Passes each event to `handler`, and any panic to `exceptions`, see `exception::guard()`.
+    /// If the handler fails, the EP stops just past the failing event, so the events after
+    /// it are not reported as handled.
+    fn run_handler<H, W: WaitStrategy>(&self, handler: &mut H, mut exceptions: Option<&mut ExceptionHandler<T>>) -> Result<u64, H::Error>
+    where H: EventHandler<T> + ?Sized {
+        handler.on_start();
+
+        let result = self.run::<_, _, H::Error, W>(None, |offset, len, sequence, end_of_batch| {
+            let data: &[T] = unsafe { self.ring.get(offset, len) };
+            let last = len - 1;
+            for (i, event) in data.iter().enumerate() {
+                let event_sequence = sequence + i as u64;
+                if let Err(e) = exception::guard(|| handler.on_event(event, event_sequence, end_of_batch && i == last),
+                                                 &mut exceptions, &self.signal, &self.ring, offset + i, event_sequence) {
+                    return Err((event_sequence + 1, e));
+                }
+            }
+            Ok(())
+        }, |_| Ok(()));
+
+        handler.on_shutdown();
+        result
+    }
+
+    /// The end (exclusive) of the events which can be read from `sequence` right now,
+    /// looking no further than `limit`
+    fn available_from(&self, sequence: u64, limit: u64) -> u64 {
+        sequencer::available_from(&self.graph.as_slice()[self.token], self.cursors.as_slice(), &self.available,
+                                  sequence, limit)
+    }
+
+    /// Returns true if every dependency of this EP has stopped, see `sequencer::dependencies_stopped()`
+    fn dependencies_stopped(&self) -> bool {
+        sequencer::dependencies_stopped(&self.graph.as_slice()[self.token], self.stopped.as_slice())
+    }
+
+    /// The main processing loop shared by all the `start` methods
+    ///
+    /// `f` is called with the ring offset and length of a run of available events, the
+    /// sequence of the first event, and whether the run ends the batch (it won't if the
+    /// batch wraps around the end of the ring).  If it fails, it returns the sequence the
+    /// EP got up to (exclusive) along with the error, and the cursor stops there.
+    ///
+    /// The callback may borrow those slots from the ring.  This is safe because the
+    /// Producer task cannot invalidate these slots before we increment our cursor, and
+    /// the borrow must end when the callback returns, *before* we increment our cursor.
+    fn run<F, H, E, W: WaitStrategy>(&self, timeout: Option<Duration>, mut f: F, mut on_timeout: H) -> Result<u64, E>
+    where F: FnMut(usize, usize, u64, bool) -> Result<(), (u64, E)>, H: FnMut(u64) -> Result<(), E> {
         let capacity = self.ring.get_capacity();
 
-        let wait_strategy: W = WaitStrategy::new(capacity);
+        let wait_strategy: W = WaitStrategy::with_signal(capacity, self.signal.clone());
 
         let ref dep_eps = self.graph.as_slice()[self.token];
         let mut deps: Vec<&Padded64> = Vec::with_capacity(dep_eps.len());
@@ -75,25 +327,69 @@ impl<T: Slot> EventProcessor<T> {
         let mask: u64 = capacity as u64 - 1;
         let mut internal_cursor = cursor.load();
         let mut rollover = (false, 0);
+        let mut error: Option<E> = None;
 
         loop {
             debug!("              Current: {}, waiting on: {}", internal_cursor, internal_cursor);
 
-            let available = wait_strategy.wait_for(internal_cursor, &deps);
+            // Once halted, an EP may only stop after all of its dependencies have stopped,
+            // otherwise it would miss events they are still draining.  This is checked
+            // *before* waiting so the cursors read by the wait are final.
+            let drained = self.signal.is_alerted() && self.dependencies_stopped();
+
+            let mut available = match timeout {
+                None => wait_strategy.wait_for(internal_cursor, &deps),
+                Some(t) => match wait_strategy.wait_for_timeout(internal_cursor, &deps, t) {
+                    Some(v) => v,
+                    None => {
+                        debug!("							Timed out at {}", internal_cursor);
+                        match on_timeout(internal_cursor) {
+                            Err(e) => {
+                                error = Some(e);
+                                break
+                            },
+                            Ok(_) if drained => break,
+                            Ok(_) => continue
+                        }
+                    }
+                }
+            };
             debug!("							Available: {}", available);
 
+            // With several producers, sequences below the root cursor may be claimed
+            // but not yet written, so only read up to the last contiguous published slot
+            let claimed = available;
+            if let Some(ref published) = self.available {
+                available = published.highest_published(internal_cursor, available);
+            }
+
+            if available == internal_cursor {
+                if drained {
+                    break;
+                }
+
+                // The wait strategy returns straight away while the next slot is claimed
+                // but unpublished, so give the producer a chance to finish writing it
+                // rather than spinning through wait_for()
+                if claimed > internal_cursor {
+                    thread::yield_now();
+                }
+                continue;
+            }
+
             let from = (internal_cursor & mask) as usize;
             let mut to = (available & mask) as usize;
 
             debug!("              from: {}, to: {} -- {}", from, to, (to < from));
+            // No second call is needed if the batch ends exactly at the end of the ring
             if to < from {
                 debug!("						ROLLOVER");
-                rollover = (true, to);
+                rollover = (to > 0, to);
                 to = capacity;
             } else if (to == from) && (internal_cursor < available) {
                 //complete buffer request
                 debug!("						ROLLOVER (total) -- ({} == {}) && ({} < {})", to, from, internal_cursor, available);
-                rollover = (true, to);
+                rollover = (to > 0, to);
                 to = capacity;
             } else if to == from {
                 debug!("						WTF to == from    -- ({} == {}) && ({} < {})", to, from, internal_cursor, available);
@@ -102,34 +398,110 @@ impl<T: Slot> EventProcessor<T> {
 
             debug!("              Post-modification from: {}, to: {} -- {}", from, to, (to < from));
 
-            // This is safe because the Producer task cannot invalidate these slots
-            // before we increment our cursor.  Since the slice is borrowed out, we
-            // know it will be returned after the function call ends.  The slice will
-            // be dropped after the unsafe block, and *then* we increment our cursor
-            let mut status = unsafe {
-                let data: &[T] = self.ring.get(from, to);
-                f(data)
-            };
+            let mut status = f(from, to - from, internal_cursor, !rollover.0);
 
             if rollover.0 {
-                debug!("ROlLOVER GET");
-                status = unsafe {
-                    let data: &[T] = self.ring.get(0, rollover.1);
-                    f(data)
-                };
+                // If the first half failed, the EP is stopping, so the second half is skipped
+                if status.is_ok() {
+                    debug!("ROlLOVER GET");
+                    status = f(0, rollover.1, internal_cursor + (to - from) as u64, true);
+                }
                 rollover = (false,0);
             }
 
+            // A failing EP must not claim to have processed anything past the failure
+            if let Err((end, _)) = status {
+                available = end;
+            }
+
+            // Must be recorded before the producer is allowed to reuse the stamps
+            if let Some(ref latency) = self.latency {
+                latency.record(self.token, internal_cursor, available);
+            }
+
             internal_cursor = available;
             cursor.store(internal_cursor);
+            self.signal.notify();   // wake any blocked EPs which depend on us
             debug!("					Finished processing event.  Cursor @ {} ({})", available, available & mask);
 
             match status {
-                Err(_) => break,
+                Err((_, e)) => {
+                    error = Some(e);
+                    break
+                },
                 Ok(_) => {}
             };
 
         }
+
+        self.stopped.as_slice()[self.token + 1].store(true, Ordering::SeqCst);
+        self.signal.notify();   // wake any blocked EPs which are draining behind us
         debug!("BusyWait::end");
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(internal_cursor)
+        }
+    }
+}
+
+/// An EventProcessor can be consumed as a futures `Stream` of events
+///
+/// This lets an EP live inside an async task instead of dedicating a thread to one of
+/// the `start` loops.  Each item is an event's sequence and a clone of the event, and
+/// the EP's cursor moves past the event as soon as it has been yielded.  When no event
+/// is available, the task is parked and woken by the next publish (or by a dependency
+/// moving its cursor).  The stream ends once the Turbine has been halted and the EP has
+/// drained, just like `start()`.
+///
+/// ## Example
+///
+///```
+///let events = event_processor.for_each(|(sequence, event): (u64, TestSlot)| {
+///    println!("{}: {}", sequence, event.value);
+///    Ok(())
+///});
+///```
+impl<T: Slot + Clone> Stream for EventProcessor<T> {
+    type Item = (u64, T);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<(u64, T)>, ()> {
+        let ref cursor = (*self.cursors).as_slice()[self.token + 1];
+        let sequence = cursor.load();
+        let mut parked = false;
+
+        loop {
+            // See run(), this must be checked before looking for events
+            let drained = self.signal.is_alerted() && self.dependencies_stopped();
+
+            if self.available_from(sequence, sequence + 1) > sequence {
+                break;
+            }
+            if drained {
+                self.stopped.as_slice()[self.token + 1].store(true, Ordering::SeqCst);
+                self.signal.notify();
+                return Ok(Async::Ready(None));
+            }
+            if parked {
+                return Ok(Async::NotReady);
+            }
+
+            // Look again once parked, in case an event was published in between
+            self.signal.park();
+            parked = true;
+        }
+
+        // This is safe because the Producer cannot overwrite the slot before we
+        // increment our cursor, and the event is cloned out of it before that
+        let event: T = unsafe { self.ring.get((sequence & (self.ring.get_capacity() as u64 - 1)) as usize, 1)[0].clone() };
+
+        if let Some(ref latency) = self.latency {
+            latency.record(self.token, sequence, sequence + 1);
+        }
+        cursor.store(sequence + 1);
+        self.signal.notify();
+
+        Ok(Async::Ready(Some((sequence, event))))
     }
 }
@@ -0,0 +1,116 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use ringbuffer::{RingBuffer, Slot};
+use waitstrategy::WaitSignal;
+
+/// What an EventProcessor should do after an EventHandler panicked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExceptionAction {
+    /// Skip the event and carry on with the next one
+    Continue,
+
+    /// Skip the event and halt the whole Turbine.  Like `Turbine::halt()`, every EP
+    /// (including this one) drains what has already been published before stopping.
+    Halt
+}
+
+/// A strategy for dealing with EventHandlers which panic
+///
+/// A panicking handler would take its EventProcessor down with it.  The EP's cursor
+/// would then never move again and the writer would eventually spin forever waiting
+/// for a free slot.  Every `start` method therefore catches panics: by default they are
+/// treated like `HaltOnException`, while `EventProcessor::start_with_exception_handler()`
+/// asks the given ExceptionHandler what to do.  Either way, the failing event is
+/// skipped, so the EP's cursor keeps moving.
+///
+/// `LogAndContinue` and `HaltOnException` cover the common cases.
+///
+///##Example
+///
+///```
+///struct DeadLetters {
+///  failed: Vec<u64>
+///}
+///
+///impl ExceptionHandler<TestSlot> for DeadLetters {
+///  fn on_exception(&mut self, _: Box<Any + Send>, _: &TestSlot, sequence: u64) -> ExceptionAction {
+///    self.failed.push(sequence);
+///    ExceptionAction::Continue
+///  }
+///}
+///```
+pub trait ExceptionHandler<T> {
+    /// Called on the EventProcessor's thread after handling `event` panicked
+    ///
+    /// `payload` is the value the handler panicked with, and `sequence` is the event's
+    /// position in the stream of events written to Turbine.
+    fn on_exception(&mut self, payload: Box<Any + Send>, event: &T, sequence: u64) -> ExceptionAction;
+}
+
+impl<T, X: ExceptionHandler<T> + ?Sized> ExceptionHandler<T> for Box<X> {
+    fn on_exception(&mut self, payload: Box<Any + Send>, event: &T, sequence: u64) -> ExceptionAction {
+        (**self).on_exception(payload, event, sequence)
+    }
+}
+
+/// Extract the message from a panic payload, if it has one
+pub fn panic_message(payload: &Box<Any + Send>) -> &str {
+    match payload.downcast_ref::<&'static str>() {
+        Some(s) => s,
+        None => match payload.downcast_ref::<String>() {
+            Some(s) => &s[..],
+            None => "Box<Any>"
+        }
+    }
+}
+
+/// Logs the panic and skips the event
+#[derive(Clone, Copy, Debug)]
+pub struct LogAndContinue;
+
+impl<T> ExceptionHandler<T> for LogAndContinue {
+    fn on_exception(&mut self, payload: Box<Any + Send>, _: &T, sequence: u64) -> ExceptionAction {
+        error!("EventHandler panicked at sequence {}, skipping: {}", sequence, panic_message(&payload));
+        ExceptionAction::Continue
+    }
+}
+
+/// Logs the panic and halts the Turbine, so the pipeline drains and stops
+#[derive(Clone, Copy, Debug)]
+pub struct HaltOnException;
+
+impl<T> ExceptionHandler<T> for HaltOnException {
+    fn on_exception(&mut self, payload: Box<Any + Send>, _: &T, sequence: u64) -> ExceptionAction {
+        error!("EventHandler panicked at sequence {}, halting: {}", sequence, panic_message(&payload));
+        ExceptionAction::Halt
+    }
+}
+
+/// Call `f`, which handles the events starting at ring `offset` and `sequence`, and
+/// catch any panic
+///
+/// The panic is passed to `exceptions` (or to `HaltOnException` if there is none)
+/// along with the first event `f` was handling, and the Turbine is halted if it says
+/// so.  The events are then treated as handled, so Ok is returned.
+pub fn guard<T, E, F>(f: F, exceptions: &mut Option<&mut ExceptionHandler<T>>, signal: &WaitSignal,
+                      ring: &RingBuffer<T>, offset: usize, sequence: u64) -> Result<(), E>
+where T: Slot, F: FnOnce() -> Result<(), E> {
+    // The caller may be left inconsistent by a panic, which is for the ExceptionHandler
+    // to judge
+    let payload = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(status) => return status,
+        Err(payload) => payload
+    };
+
+    // This is safe because the caller has not moved its cursor past the event yet,
+    // and any borrow `f` had of the slot ended when it unwound
+    let event = unsafe { &ring.get(offset, 1)[0] };
+    let action = match *exceptions {
+        Some(ref mut x) => x.on_exception(payload, event, sequence),
+        None => HaltOnException.on_exception(payload, event, sequence)
+    };
+    if action == ExceptionAction::Halt {
+        signal.alert();
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::error::Error;
+use std::fmt;
+
+/// Reasons a dependency graph cannot be finalized
+///
+/// All values are EP tokens, as returned by `Turbine::ep_new()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphError {
+    /// `processor` depends on a token which does not belong to any EventProcessor
+    UnknownDependency { processor: usize, dependency: usize },
+
+    /// The EventProcessor depends on itself
+    SelfDependency(usize),
+
+    /// The same dependency was added to `processor` more than once
+    DuplicateDependency { processor: usize, dependency: usize },
+
+    /// The EventProcessors form a cycle.  Each token depends on the next, and the last
+    /// depends on the first.
+    Cycle(Vec<usize>),
+
+    /// `processor` modifies events in place, but `other` neither depends on it nor is one
+    /// of its dependencies, so both could access the same slot at the same time
+    UnorderedMutation { processor: usize, other: usize }
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GraphError::UnknownDependency { processor, dependency } =>
+                write!(f, "EventProcessor {} depends on unknown EventProcessor {}", processor, dependency),
+            GraphError::SelfDependency(token) =>
+                write!(f, "EventProcessor {} depends on itself", token),
+            GraphError::DuplicateDependency { processor, dependency } =>
+                write!(f, "EventProcessor {} depends on EventProcessor {} more than once", processor, dependency),
+            GraphError::Cycle(ref tokens) =>
+                write!(f, "EventProcessors form a cycle: {:?}", tokens),
+            GraphError::UnorderedMutation { processor, other } =>
+                write!(f, "EventProcessor {} modifies events but is not ordered with EventProcessor {}", processor, other)
+        }
+    }
+}
+
+impl Error for GraphError {
+    fn description(&self) -> &str {
+        match *self {
+            GraphError::UnknownDependency { .. } => "dependency on an unknown EventProcessor",
+            GraphError::SelfDependency(_) => "EventProcessor depends on itself",
+            GraphError::DuplicateDependency { .. } => "duplicate dependency",
+            GraphError::Cycle(_) => "dependency cycle",
+            GraphError::UnorderedMutation { .. } => "mutable EventProcessor is not ordered with another EventProcessor"
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Unvisited,
+    InProgress,
+    Done
+}
+
+/// Validate a dependency graph, expressed as a list of dependencies per EP token
+///
+/// Checks (in order) for unknown tokens, self-dependencies and duplicate edges on
+/// each EP, then for cycles across the whole graph, and finally that every EP flagged
+/// in `mutable` is ordered (directly or transitively) with every other EP.  The first
+/// problem found is returned.
+pub fn validate(graph: &Vec<Vec<usize>>, mutable: &Vec<bool>) -> Result<(), GraphError> {
+    for (processor, deps) in graph.iter().enumerate() {
+        for (i, &dependency) in deps.iter().enumerate() {
+            if dependency >= graph.len() {
+                return Err(GraphError::UnknownDependency { processor: processor, dependency: dependency });
+            }
+            if dependency == processor {
+                return Err(GraphError::SelfDependency(processor));
+            }
+            if deps[..i].contains(&dependency) {
+                return Err(GraphError::DuplicateDependency { processor: processor, dependency: dependency });
+            }
+        }
+    }
+
+    let mut marks = vec![Mark::Unvisited; graph.len()];
+    let mut path = Vec::with_capacity(graph.len());
+    for processor in 0..graph.len() {
+        if marks[processor] == Mark::Unvisited {
+            try!(visit(graph, processor, &mut marks, &mut path));
+        }
+    }
+
+    if mutable.iter().any(|&m| m) {
+        let reachable: Vec<Vec<bool>> = (0..graph.len()).map(|p| dependencies_of(graph, p)).collect();
+        for (processor, _) in mutable.iter().enumerate().filter(|&(_, &m)| m) {
+            for other in 0..graph.len() {
+                if other != processor && !reachable[processor][other] && !reachable[other][processor] {
+                    return Err(GraphError::UnorderedMutation { processor: processor, other: other });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find every EP `processor` depends on, directly or transitively
+///
+/// Returns a flag per EP token.  Each EP is only visited once, so shared ancestors
+/// (e.g. in a diamond) do not make this exponential.
+fn dependencies_of(graph: &Vec<Vec<usize>>, processor: usize) -> Vec<bool> {
+    let mut visited = vec![false; graph.len()];
+    let mut stack: Vec<usize> = graph[processor].clone();
+    while let Some(dependency) = stack.pop() {
+        if visited[dependency] == false {
+            visited[dependency] = true;
+            stack.extend(graph[dependency].iter().cloned());
+        }
+    }
+    visited
+}
+
+/// Depth-first search used by `validate()` to find cycles
+///
+/// `path` holds the EPs currently being visited, so when an in-progress EP is
+/// reached again the cycle is the tail of `path` starting at that EP.
+fn visit(graph: &Vec<Vec<usize>>, processor: usize, marks: &mut Vec<Mark>, path: &mut Vec<usize>) -> Result<(), GraphError> {
+    marks[processor] = Mark::InProgress;
+    path.push(processor);
+
+    for &dependency in graph[processor].iter() {
+        match marks[dependency] {
+            Mark::Done => {},
+            Mark::Unvisited => try!(visit(graph, dependency, marks, path)),
+            Mark::InProgress => {
+                let start = path.iter().position(|&p| p == dependency).unwrap();
+                return Err(GraphError::Cycle(path[start..].to_vec()));
+            }
+        }
+    }
+
+    path.pop();
+    marks[processor] = Mark::Done;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{validate, GraphError};
+
+    #[test]
+    fn valid_diamond() {
+        // e1 <-- e2, e3 <-- e4
+        let graph = vec![vec![], vec![0], vec![0], vec![1, 2]];
+        assert!(validate(&graph, &vec![false; 4]) == Ok(()));
+    }
+
+    #[test]
+    fn invalid_edges() {
+        assert!(validate(&vec![vec![], vec![5]], &vec![]) == Err(GraphError::UnknownDependency { processor: 1, dependency: 5 }));
+        assert!(validate(&vec![vec![], vec![1]], &vec![]) == Err(GraphError::SelfDependency(1)));
+        assert!(validate(&vec![vec![], vec![0, 0]], &vec![]) == Err(GraphError::DuplicateDependency { processor: 1, dependency: 0 }));
+    }
+
+    #[test]
+    fn cycle() {
+        // e1 --> e2 --> e3 --> e2
+        let graph = vec![vec![1], vec![2], vec![1]];
+        assert!(validate(&graph, &vec![false; 3]) == Err(GraphError::Cycle(vec![1, 2])));
+    }
+
+    #[test]
+    fn mutable_ordering() {
+        // e1 <-- e2, e3 <-- e4
+        let graph = vec![vec![], vec![0], vec![0], vec![1, 2]];
+
+        // e1 and e4 are ordered with everything, e2 and e3 run side by side
+        assert!(validate(&graph, &vec![true, false, false, true]) == Ok(()));
+        assert!(validate(&graph, &vec![false, true, false, false]) == Err(GraphError::UnorderedMutation { processor: 1, other: 2 }));
+    }
+
+    #[test]
+    fn mutable_stacked_diamonds() {
+        // e0 <-- (e1, e2) <-- (e3, e4) <-- ... each layer depending on both EPs of the
+        // layer before.  Walking every path would take 2^40 steps.
+        let mut graph = vec![vec![], vec![0], vec![0]];
+        for layer in 1..40 {
+            let (a, b) = (layer * 2 - 1, layer * 2);
+            graph.push(vec![a, b]);
+            graph.push(vec![a, b]);
+        }
+        let last = graph.len() - 1;
+        graph.push(vec![last - 1, last]);
+
+        let mut mutable = vec![false; graph.len()];
+        mutable[0] = true;
+        mutable[graph.len() - 1] = true;
+        assert!(validate(&graph, &mutable) == Ok(()));
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+use waitstrategy::WaitSignal;
+
+/// A cloneable handle which can halt a Turbine from any thread
+///
+/// Halting raises the alert flag shared by every WaitStrategy.  EventProcessors
+/// finish processing whatever has already been published (and whatever their
+/// dependencies are still draining), then return from `start()`.
+///
+/// Obtained from `Turbine::halt_handle()`.
+#[derive(Clone)]
+pub struct HaltHandle {
+    signal: Arc<WaitSignal>
+}
+
+impl HaltHandle {
+    /// Instantiate a new HaltHandle.  This is for internal use only.
+    pub fn new(signal: Arc<WaitSignal>) -> HaltHandle {
+        HaltHandle {
+            signal: signal
+        }
+    }
+
+    /// Halt the Turbine.  Calling this more than once has no further effect.
+    pub fn halt(&self) {
+        self.signal.alert();
+    }
+
+    /// Returns true if the Turbine has been halted
+    pub fn is_halted(&self) -> bool {
+        self.signal.is_alerted()
+    }
+}
@@ -0,0 +1,60 @@
+/// A trait for consuming events one at a time, as an alternative to closures
+///
+/// Closures passed to `EventProcessor::start()` receive whole slices, and may be
+/// called twice per batch when it wraps around the end of the ring.  An EventHandler
+/// instead receives each event along with its sequence number, and is told which
+/// event ends the batch.  This is the natural place to flush buffered downstream
+/// writes (e.g. to disk or a socket), since the next event may be a while away.
+///
+///##Example
+///
+///```
+///struct Journaller {
+///  writer: BufWriter<File>
+///}
+///
+///impl EventHandler<TestSlot> for Journaller {
+///  type Error = io::Error;
+///
+///  fn on_event(&mut self, event: &TestSlot, sequence: u64, end_of_batch: bool) -> Result<(), io::Error> {
+///    try!(write!(self.writer, "{}: {}\n", sequence, event.value));
+///    if end_of_batch {
+///      try!(self.writer.flush());
+///    }
+///    Ok(())
+///  }
+///}
+///```
+pub trait EventHandler<T> {
+    /// The error returned by `on_event()`, which stops the EventProcessor
+    type Error;
+
+    /// Handle a single event
+    ///
+    /// `sequence` is the event's position in the stream of events written to Turbine.
+    /// `end_of_batch` is true for the last event currently available to this
+    /// EventProcessor.  Returning an Err shuts down the EventProcessor.
+    fn on_event(&mut self, event: &T, sequence: u64, end_of_batch: bool) -> Result<(), Self::Error>;
+
+    /// Called once, on the EventProcessor's thread, before the first event
+    fn on_start(&mut self) {}
+
+    /// Called once, on the EventProcessor's thread, after the last event
+    fn on_shutdown(&mut self) {}
+}
+
+impl<T, H: EventHandler<T> + ?Sized> EventHandler<T> for Box<H> {
+    type Error = H::Error;
+
+    fn on_event(&mut self, event: &T, sequence: u64, end_of_batch: bool) -> Result<(), H::Error> {
+        (**self).on_event(event, sequence, end_of_batch)
+    }
+
+    fn on_start(&mut self) {
+        (**self).on_start()
+    }
+
+    fn on_shutdown(&mut self) {
+        (**self).on_shutdown()
+    }
+}
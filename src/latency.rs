@@ -0,0 +1,246 @@
+use std::sync::atomic::Ordering::{Relaxed, Release, Acquire};
+use std::time::Instant;
+use std::cmp::min;
+use atomicnum::AtomicNum;
+
+/// Values below this are recorded exactly; above it, each power of two is split into
+/// this many buckets, so every bucket is within ~6% of the values it holds
+const SUB_BUCKETS: u64 = 16;
+const SUB_BUCKET_BITS: u64 = 4;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS as usize;
+
+/// The bucket holding `value`
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros() as u64;
+    let shift = exponent - SUB_BUCKET_BITS;
+    ((shift + 1) * SUB_BUCKETS + ((value >> shift) & (SUB_BUCKETS - 1))) as usize
+}
+
+/// The smallest value which falls into bucket `index`
+fn bucket_value(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    (SUB_BUCKETS + index % SUB_BUCKETS) << shift
+}
+
+/// The largest value which falls into bucket `index`
+fn bucket_limit(index: usize) -> u64 {
+    match index + 1 < BUCKETS {
+        true => bucket_value(index + 1) - 1,
+        false => 18446744073709551615
+    }
+}
+
+/// A lock-free, log-linear histogram of nanosecond latencies
+///
+/// Like an HDR histogram, buckets are exact for small values and otherwise have a
+/// fixed relative precision, so the histogram covers every u64 in under 1000 buckets.
+struct Histogram {
+    buckets: Vec<AtomicNum<u64>>,
+    total: AtomicNum<u64>,
+    max: AtomicNum<u64>
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: (0..BUCKETS).map(|_| AtomicNum::new(0)).collect(),
+            total: AtomicNum::new(0),
+            max: AtomicNum::new(0)
+        }
+    }
+
+    /// Counters are only read by snapshots, which don't need to be consistent with
+    /// each other or with the ring, so they are updated with relaxed ordering
+    fn record(&self, value: u64) {
+        self.buckets[bucket_index(value)].fetch_add(1, Relaxed);
+        self.total.fetch_add(value, Relaxed);
+
+        let mut max = self.max.load(Relaxed);
+        while value > max {
+            let previous = self.max.compare_and_swap(max, value, Relaxed);
+            if previous == max {
+                break;
+            }
+            max = previous;
+        }
+    }
+
+    fn snapshot(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            counts: self.buckets.iter().map(|b| b.load(Relaxed)).collect(),
+            total: self.total.load(Relaxed),
+            max: self.max.load(Relaxed)
+        }
+    }
+}
+
+/// A snapshot of an EventProcessor's publish-to-handle latencies, in nanoseconds
+///
+/// Values are reported at the precision of the histogram's buckets (within ~6%).
+/// Percentiles are rounded up to the top of their bucket, so they never understate
+/// the latency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64
+}
+
+impl LatencyHistogram {
+    /// The number of events recorded
+    pub fn count(&self) -> u64 {
+        self.counts.iter().fold(0, |sum, &c| sum + c)
+    }
+
+    /// The mean latency, or 0 if nothing was recorded
+    pub fn mean(&self) -> u64 {
+        match self.count() {
+            0 => 0,
+            count => self.total / count
+        }
+    }
+
+    /// The highest latency recorded (exact)
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// The latency below which `percentile` percent of events fall, e.g. `percentile(99.0)`
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+
+        let target = ((percentile / 100.0) * count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (index, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= target && c > 0 {
+                return min(bucket_limit(index), self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Publish timestamps for every slot, and a latency histogram for every EP
+///
+/// Created when the graph is finalized if `Turbine::track_latency()` was called.
+/// Producers stamp each sequence just before publishing it, and EPs record the time
+/// since the stamp once they have handled the sequence, before moving their cursor.
+/// The stamp therefore cannot be overwritten before it has been read.
+pub struct Latency {
+    base: Instant,
+    stamps: Vec<AtomicNum<u64>>,
+    mask: u64,
+    histograms: Vec<Histogram>
+}
+
+impl Latency {
+    pub fn new(ring_size: usize, processors: usize) -> Latency {
+        Latency {
+            base: Instant::now(),
+            stamps: (0..ring_size).map(|_| AtomicNum::new(0)).collect(),
+            mask: (ring_size - 1) as u64,
+            histograms: (0..processors).map(|_| Histogram::new()).collect()
+        }
+    }
+
+    /// Nanoseconds since the Latency was created
+    #[inline]
+    fn now(&self) -> u64 {
+        let elapsed = self.base.elapsed();
+        elapsed.as_secs() * 1000000000 + elapsed.subsec_nanos() as u64
+    }
+
+    /// Stamp the sequences `[from, to)` with the current time
+    pub fn stamp(&self, from: u64, to: u64) {
+        let now = self.now();
+        for sequence in from..to {
+            self.stamps[(sequence & self.mask) as usize].store(now, Release);
+        }
+    }
+
+    /// Record that the EP with this token has handled the sequences `[from, to)`
+    ///
+    /// Does nothing if there is no histogram for `token`.
+    pub fn record(&self, token: usize, from: u64, to: u64) {
+        let histogram = match self.histograms.get(token) {
+            Some(histogram) => histogram,
+            None => return
+        };
+
+        let now = self.now();
+        for sequence in from..to {
+            let stamp = self.stamps[(sequence & self.mask) as usize].load(Acquire);
+            histogram.record(now.saturating_sub(stamp));
+        }
+    }
+
+    /// A snapshot of the histogram for the EP with this token, if there is one
+    pub fn histogram(&self, token: usize) -> Option<LatencyHistogram> {
+        self.histograms.get(token).map(|h| h.snapshot())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{bucket_index, bucket_value, bucket_limit, Histogram, Latency, BUCKETS};
+
+    #[test]
+    fn buckets() {
+        for v in 0..16 {
+            assert!(bucket_index(v) == v as usize);
+        }
+        assert!(bucket_index(16) == 16);
+        assert!(bucket_index(32) == 32);
+        assert!(bucket_index(33) == 32);
+        assert!(bucket_index(18446744073709551615) == BUCKETS - 1);
+
+        for index in 0..BUCKETS {
+            assert!(bucket_index(bucket_value(index)) == index);
+            assert!(bucket_index(bucket_limit(index)) == index);
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let h = Histogram::new();
+        for v in 1..101 {
+            h.record(v * 1000);
+        }
+
+        let snapshot = h.snapshot();
+        assert!(snapshot.count() == 100);
+        assert!(snapshot.max() == 100000);
+        assert!(snapshot.mean() == 50500);
+
+        // Rounded up, within the precision of the buckets, but never beyond the max
+        let p50 = snapshot.percentile(50.0);
+        assert!(p50 >= 50000 && p50 <= 53000);
+        let p99 = snapshot.percentile(99.0);
+        assert!(p99 >= 99000 && p99 <= 100000);
+        assert!(snapshot.percentile(100.0) == 100000);
+    }
+
+    #[test]
+    fn unknown_token() {
+        let latency = Latency::new(8, 1);
+        latency.stamp(0, 4);
+        latency.record(0, 0, 4);
+        latency.record(5, 0, 4);
+
+        assert!(latency.histogram(0).unwrap().count() == 4);
+        assert!(latency.histogram(5).is_none());
+    }
+}
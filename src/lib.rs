@@ -6,8 +6,6 @@
 #![feature(convert)]
 // for atomicnum
 #![feature(core_intrinsics)]
-// for usleep
-#![feature(libc)]
 
 //! Turbine is a high-performance, non-locking, isizeer-task communication library.
 //!
@@ -34,6 +32,7 @@
 //! - Channels are much simpler to use
 //! - Channels are more efficient if you have low or inconsistent communication requirements
 //! - Channels can be MPSC (multi-producer, single-consumer) while Turbine is SPMC
+//!   (multiple producers are supported via `Turbine::new_multi_producer()`, but are slower)
 //! - Turbine requires significant memory overhead to initialize (the ring buffer)
 //!
 //! ```
@@ -61,11 +60,11 @@
 //!   };
 //!
 //!   // Finalize and retrieve an EventProcessor
-//!   let event_processor = turbine.ep_finalize(ep_builder);
+//!   let event_processor = turbine.ep_finalize(ep_builder).unwrap();
 //!
 //!   // Spawn a new thread, wait for data to arrive
 //!   spawn(move|| {
-//!   	event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+//!   	event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
 //!   	    // ... process work here ... //
 //!   	});
 //!   });
@@ -79,31 +78,86 @@
 #[macro_use]
 extern crate log;
 
-#[cfg(test)] extern crate libc;
+#[cfg(any(test, target_os = "linux"))] extern crate libc;
+extern crate futures;
 #[cfg(test)] extern crate time;
 #[cfg(test)] extern crate rand;
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::cmp::min;
+use std::time::Duration;
 
 pub use ringbuffer::{RingBuffer, Slot};
-pub use waitstrategy::{WaitStrategy, BusyWait};
+pub use waitstrategy::{WaitStrategy, WaitSignal, BusyWait, BlockingWait, YieldingWait, SleepingWait};
 pub use eventprocessor::EventProcessor;
+pub use sequencer::MultiProducer;
+pub use halt::HaltHandle;
+pub use claim::{Claim, BatchClaim};
+pub use graph::GraphError;
+pub use error::TurbineError;
+pub use handler::EventHandler;
+pub use workerpool::{WorkerPool, Worker};
+pub use builder::{PipelineBuilder, Pipeline, BoxedHandler};
+pub use spawn::ProcessorThread;
+pub use affinity::{AffinityConfig, pin_current_thread};
+pub use exception::{ExceptionHandler, ExceptionAction, LogAndContinue, HaltOnException};
+pub use stats::{TurbineStats, ProcessorStats, SubscriberStats, ProducerStats, StatsHandle};
+pub use backpressure::{Stall, Bottleneck};
+pub use latency::LatencyHistogram;
+pub use poller::{EventPoller, PollState};
+pub use subscriber::{Subscriber, StartAt};
 #[doc(hidden)] pub use paddedatomics::Padded64;
 
+use sequencer::{self, AvailableBuffer, SinkProducer, minimum_cursor};
+use claim::Publisher;
+use backpressure::{Backpressure, Waiting};
+use latency::Latency;
+use subscriber::DynamicCursors;
+use futures::{Sink, StartSend, Poll, Async};
+
 mod atomicnum;
 mod eventprocessor;
 mod waitstrategy;
 mod paddedatomics;
 mod ringbuffer;
+mod sequencer;
+mod halt;
+mod claim;
+mod graph;
+mod error;
+mod handler;
+mod workerpool;
+mod builder;
+mod spawn;
+mod affinity;
+mod exception;
+mod stats;
+mod backpressure;
+mod latency;
+mod poller;
+mod subscriber;
 
 /// The main Turbine structure, which controls the operation of this library.
 pub struct Turbine<T> {
     finalized: bool,
     epb: Vec<Vec<usize>>,
+    mutable: Vec<bool>,
+    pools: Vec<Option<usize>>,
+    names: Vec<String>,
+    taken: Vec<bool>,
     graph: Arc<Vec<Vec<usize>>>,
     cursors: Arc<Vec<Padded64>>,
+    stopped: Arc<Vec<AtomicBool>>,
     ring: Arc<RingBuffer<T>>,
+    available: Option<Arc<AvailableBuffer>>,
+    producer: Option<MultiProducer<T>>,
+    dynamic: Arc<DynamicCursors>,
+    signal: Arc<WaitSignal>,
+    backpressure: Arc<Backpressure>,
+    track_latency: bool,
+    latency: Option<Arc<Latency>>,
+    parked_send: Option<Waiting>,
     current_pos: u64,
     size: usize,
     mask: u64,
@@ -134,9 +188,22 @@ impl<T: Slot> Turbine<T> {
         Turbine::<T> {
             finalized: false,
             epb: epb,
+            mutable: Vec::with_capacity(8),
+            pools: Vec::with_capacity(8),
+            names: Vec::with_capacity(8),
+            taken: Vec::with_capacity(8),
             graph: Arc::new(vec![]),
             cursors: Arc::new(vec![]),
+            stopped: Arc::new(vec![]),
             ring: Arc::new(RingBuffer::<T>::new(ring_size)),
+            available: None,
+            producer: None,
+            dynamic: Arc::new(DynamicCursors::new()),
+            signal: Arc::new(WaitSignal::new()),
+            backpressure: Arc::new(Backpressure::new()),
+            track_latency: false,
+            latency: None,
+            parked_send: None,
             current_pos: 0,
             size: ring_size,
             mask: (ring_size - 1) as u64,
@@ -144,6 +211,29 @@ impl<T: Slot> Turbine<T> {
         }
     }
 
+    /// Create a new Turbine which accepts writes from several threads.
+    ///
+    /// This behaves like `Turbine::new()`, except that sequences are claimed with
+    /// an atomic fetch-add on the root cursor and each slot carries a publication
+    /// marker.  Once the graph has been finalized, any number of `MultiProducer`
+    /// handles may be obtained with `producer()` and moved into other threads.
+    ///
+    /// Multi-producer mode is slower than single producer mode (EventProcessors
+    /// must check each slot's marker before reading it), so only use it if you
+    /// actually have several writers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut t: Turbine<TestSlot> = Turbine::new_multi_producer(1024);
+    /// ```
+    ///
+    pub fn new_multi_producer(ring_size: usize) -> Turbine<T> {
+        let mut t = Turbine::new(ring_size);
+        t.available = Some(Arc::new(AvailableBuffer::new(ring_size)));
+        t
+    }
+
     /// Add a new EventProcessor to the dependency graph.
     ///
     /// Event processors can be thought of as "consumers" or "readers" of the
@@ -152,8 +242,8 @@ impl<T: Slot> Turbine<T> {
     /// as you wish.
     ///
     /// This method returns a Result.  On success, it contains a usize which
-    /// represents the isizeernal index of the EP.  On failure, the Err is empty.
-    /// Failure occurs if the graph has been `finalized`
+    /// represents the isizeernal index of the EP.  Failure occurs if the graph has
+    /// been `finalized` (`TurbineError::AlreadyFinalized`)
     ///
     ///## Example
     ///
@@ -167,16 +257,48 @@ impl<T: Slot> Turbine<T> {
     ///}
     ///```
     ///
-    pub fn ep_new(&mut self) -> Result<usize, ()> {
+    pub fn ep_new(&mut self) -> Result<usize, TurbineError> {
         match self.finalized {
-            true => Err(()),
+            true => Err(TurbineError::AlreadyFinalized),
             false => {
                     self.epb.push(vec![]);
+                    self.mutable.push(false);
+                    self.pools.push(None);
+                    self.names.push(format!("turbine-ep-{}", self.epb.len() - 1));
+                    self.taken.push(false);
                     Ok(self.epb.len() - 1)
             }
         }
     }
 
+    /// Add a new WorkerPool of `workers` workers to the dependency graph.
+    ///
+    /// A WorkerPool is a single node of the graph, just like an EventProcessor, and
+    /// the returned token may be used with `ep_depends()` in either position.  Unlike an
+    /// EventProcessor, each event is handled by only one of the pool's workers.  This
+    /// lets several threads share a CPU-heavy stage, while later EPs still see every
+    /// event once the pool is done with it.
+    ///
+    /// The token must be finalized with `pool_finalize()` instead of `ep_finalize()`.
+    ///
+    /// This method returns a Result.  Failure occurs if the graph has been `finalized`
+    /// (`TurbineError::AlreadyFinalized`).  Panics if `workers` is zero.
+    ///
+    ///## Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///let decode = t.ep_new_pool(4).unwrap();
+    ///let journal = t.ep_new().unwrap();
+    ///t.ep_depends(journal, decode).unwrap();   // journal sees each event once decoded
+    ///```
+    pub fn ep_new_pool(&mut self, workers: usize) -> Result<usize, TurbineError> {
+        assert!(workers > 0, "A WorkerPool needs at least one worker");
+        let token = try!(self.ep_new());
+        self.pools[token] = Some(workers);
+        Ok(token)
+    }
+
     /// Add `dep` as a dependency to the EventProcessor at `epb_index`.
     ///
     /// EventProcessors may "depend" on one or more EventProcessors.  This links
@@ -189,11 +311,12 @@ impl<T: Slot> Turbine<T> {
     ///
     /// EPs may be linked in arbitrarily complex chains (e.g. several levels deep,
     /// multiple dependencies, dependencies on different levels of the tree, etc).
-    /// However, there is currently *no* protection against cylces.  Behavior is
-    /// undefined (likely a fatal error) if you isizeroduce a cycle.
+    /// Dependencies are not checked here; cycles, self-dependencies, duplicates and
+    /// unknown tokens are reported as a `GraphError` when the graph is finalized.
     ///
-    /// This method returns a Result.  Both success and error Results are empty.
-    /// Failure occurs if the graph has been `finalized`.
+    /// This method returns a Result.  Failure occurs if the graph has been `finalized`
+    /// (`TurbineError::AlreadyFinalized`) or if `epb_index` does not belong to any EP
+    /// (`TurbineError::UnknownProcessor`).
     ///
     ///## Simple Example
     ///
@@ -240,15 +363,73 @@ impl<T: Slot> Turbine<T> {
     ///```
     ///*Note: `.unwrap()` is used to make the example more readable*
     ///
-    pub fn ep_depends(&mut self, epb_index: usize, dep: usize) -> Result<(),()> {
+    pub fn ep_depends(&mut self, epb_index: usize, dep: usize) -> Result<(), TurbineError> {
         if self.finalized == true {
-            return Err(());
+            return Err(TurbineError::AlreadyFinalized);
         }
 
         if let Some(ref mut slot) = self.epb.get_mut(epb_index) {
             slot.push(dep);
             Ok(())
-        } else { Err(()) }
+        } else { Err(TurbineError::UnknownProcessor(epb_index)) }
+    }
+
+    /// Name the EventProcessor at `epb_index`, for reporting in `TurbineStats`.
+    ///
+    /// EPs are named `turbine-ep-<token>` by default.  This method returns a Result.
+    /// Failure occurs if the graph has been `finalized` (`TurbineError::AlreadyFinalized`)
+    /// or if `epb_index` does not belong to any EP (`TurbineError::UnknownProcessor`).
+    pub fn ep_name(&mut self, epb_index: usize, name: &str) -> Result<(), TurbineError> {
+        if self.finalized == true {
+            return Err(TurbineError::AlreadyFinalized);
+        }
+
+        if let Some(slot) = self.names.get_mut(epb_index) {
+            *slot = name.to_string();
+            Ok(())
+        } else { Err(TurbineError::UnknownProcessor(epb_index)) }
+    }
+
+    /// Allow the EventProcessor at `epb_index` to modify events in place.
+    ///
+    /// A mutable EP may use `EventProcessor::start_mut()`, which hands out `&mut [T]`
+    /// instead of `&[T]`.  This is useful for pipeline stages which enrich events (e.g.
+    /// unmarshalling a raw buffer) before later stages consume them.
+    ///
+    /// To make this safe, every other EP must be ordered with the mutable EP: either
+    /// it depends on the mutable EP (and only sees events after they have been modified),
+    /// or the mutable EP depends on it (and only modifies events it has finished with).
+    /// This is checked when the graph is finalized, and a `GraphError::UnorderedMutation`
+    /// is returned if two EPs could access the same slot at the same time.
+    ///
+    /// This method returns a Result.  Failure occurs if the graph has been `finalized`
+    /// (`TurbineError::AlreadyFinalized`), if `epb_index` does not belong to any EP
+    /// (`TurbineError::UnknownProcessor`) or if it belongs to a WorkerPool, which cannot
+    /// be mutable (`TurbineError::WorkerPoolToken`).
+    ///
+    ///## Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///let parser = t.ep_new().unwrap();
+    ///let logic = t.ep_new().unwrap();
+    ///
+    ///t.ep_mutable(parser).unwrap();
+    ///t.ep_depends(logic, parser).unwrap();   // logic only sees parsed events
+    ///```
+    pub fn ep_mutable(&mut self, epb_index: usize) -> Result<(), TurbineError> {
+        if self.finalized == true {
+            return Err(TurbineError::AlreadyFinalized);
+        }
+
+        match self.pools.get(epb_index) {
+            None => Err(TurbineError::UnknownProcessor(epb_index)),
+            Some(&Some(_)) => Err(TurbineError::WorkerPoolToken(epb_index)),
+            Some(&None) => {
+                self.mutable[epb_index] = true;
+                Ok(())
+            }
+        }
     }
 
     /// Finalize the isizeernal EventProcessorBuilder and obtain an EventProcessor.
@@ -263,6 +444,16 @@ impl<T: Slot> Turbine<T> {
     /// Once finalize has been called (for any EP), no further EPs or dependencies
     /// may be added.
     ///
+    /// The first call validates the whole graph.  If it contains an unknown token,
+    /// a self-dependency, a duplicate dependency or a cycle, a
+    /// `TurbineError::InvalidDependency` describing the problem is returned and the
+    /// graph is left unfinalized.  `TurbineError::UnknownProcessor` is returned if
+    /// `token` does not belong to any EP.
+    ///
+    /// Each EP can only be finalized once, since two EventProcessors for the same token
+    /// would share (and race on) a single cursor.  Later calls return
+    /// `TurbineError::AlreadyTaken`.
+    ///
     ///# Example
     ///
     ///```
@@ -274,17 +465,265 @@ impl<T: Slot> Turbine<T> {
     ///
     ///  t.ep_depends(e2, e1);	// ep2 depends on ep1
     ///
-    ///  let ep1: EventProcessor<TestSlot> = t.finalize(e1);
-    ///  let ep2 = t.finalize(e2);
+    ///  let ep1: EventProcessor<TestSlot> = t.ep_finalize(e1).unwrap();
+    ///  let ep2 = t.ep_finalize(e2).unwrap();
     ///}
     ///```
     ///*Note: `.unwrap()` is used to make the example more readable*
-    pub fn ep_finalize(&mut self, token: usize) -> EventProcessor<T> {
+    pub fn ep_finalize(&mut self, token: usize) -> Result<EventProcessor<T>, TurbineError> {
+        try!(self.validate_and_finalize());
+
+        match self.pools.get(token) {
+            None => return Err(TurbineError::UnknownProcessor(token)),
+            Some(&Some(_)) => return Err(TurbineError::WorkerPoolToken(token)),
+            Some(&None) => {}
+        }
+        if self.taken[token] == true {
+            return Err(TurbineError::AlreadyTaken(token));
+        }
+        self.taken[token] = true;
+
+        Ok(EventProcessor::<T>::new(self.ring.clone(), self.graph.clone(), self.cursors.clone(),
+                                    self.stopped.clone(), self.available.clone(), self.signal.clone(), token,
+                                    self.mutable[token], self.latency.clone()))
+    }
+
+    /// Finalize the graph (if needed) and obtain the WorkerPool for `token`.
+    ///
+    /// This is the WorkerPool equivalent of `ep_finalize()`, and validates the graph in
+    /// the same way.  `TurbineError::NotWorkerPool` is returned if `token` was created
+    /// with `ep_new()` rather than `ep_new_pool()`.  Like EPs, each pool can only be
+    /// finalized once (`TurbineError::AlreadyTaken`), otherwise two sets of workers
+    /// would each handle every event.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///let p1 = t.ep_new_pool(4).unwrap();
+    ///
+    ///for worker in t.pool_finalize(p1).unwrap().workers() {
+    ///    thread::spawn(move|| {
+    ///        worker.start::<_, _, BusyWait>(|event: &TestSlot, sequence: u64| -> Result<(), ()> {
+    ///            Ok(())
+    ///        });
+    ///    });
+    ///}
+    ///```
+    pub fn pool_finalize(&mut self, token: usize) -> Result<WorkerPool<T>, TurbineError> {
+        try!(self.validate_and_finalize());
+
+        let workers = match self.pools.get(token) {
+            None => return Err(TurbineError::UnknownProcessor(token)),
+            Some(&None) => return Err(TurbineError::NotWorkerPool(token)),
+            Some(&Some(workers)) => workers
+        };
+        if self.taken[token] == true {
+            return Err(TurbineError::AlreadyTaken(token));
+        }
+        self.taken[token] = true;
+
+        Ok(WorkerPool::<T>::new(self.ring.clone(), self.graph.clone(), self.cursors.clone(),
+                                self.stopped.clone(), self.available.clone(), self.signal.clone(), token,
+                                workers, self.latency.clone()))
+    }
+
+    /// Start building a pipeline whose first stage is `handler`.
+    ///
+    /// This is a shortcut for wiring EventHandlers together without managing EP tokens.
+    /// Further stages are added with `then()`, and `start()` creates the EPs, finalizes
+    /// the graph and spawns a thread per handler.  See `PipelineBuilder` for details.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///let pipeline = t.handle_with(journaller).then(business_logic).start::<BlockingWait>().unwrap();
+    ///
+    ///t.write(Slot::new());
+    ///let handlers = pipeline.shutdown();   // vec![Ok((journaller, 1)), Ok((business_logic, 1))]
+    ///```
+    pub fn handle_with<H>(&mut self, handler: H) -> PipelineBuilder<T, H::Error>
+    where T: 'static, H: EventHandler<T> + Send + 'static, H::Error: Send + 'static {
+        PipelineBuilder::new(self, vec![Box::new(handler)])
+    }
+
+    /// Start building a pipeline whose first stage runs all of `handlers` side by side.
+    ///
+    /// See `handle_with()`.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///// journal and replicate both see every event before business_logic does
+    ///let pipeline = t.handle_with_all(vec![journal, replicate])
+    ///                .then(business_logic)
+    ///                .start::<BusyWait>().unwrap();
+    ///```
+    pub fn handle_with_all<H>(&mut self, handlers: Vec<H>) -> PipelineBuilder<T, H::Error>
+    where T: 'static, H: EventHandler<T> + Send + 'static, H::Error: Send + 'static {
+        PipelineBuilder::new(self, builder::boxed(handlers))
+    }
+
+    /// Finalize the graph (if needed) and run each EP in `handlers` on its own thread.
+    ///
+    /// `handlers` pairs EP tokens with the handler to drive them with.  Each EP is
+    /// finalized and moved into a thread named `turbine-ep-<token>` (see
+    /// `EventProcessor::spawn()`), and the threads are returned as a `Pipeline`, which
+    /// hands the handlers back once they have stopped (e.g. with `Pipeline::shutdown()`).
+    /// Every token is checked before anything is started, so an unknown token, a
+    /// WorkerPool token or a token which was already finalized (or appears twice) fails
+    /// without starting any EPs.  If the OS refuses to create a thread, the Turbine is
+    /// halted so the threads already started drain and exit, and
+    /// `TurbineError::ThreadSpawn` is returned.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///let e1 = t.ep_new().unwrap();
+    ///let e2 = t.ep_new().unwrap();
+    ///t.ep_depends(e2, e1).unwrap();
+    ///
+    ///let pipeline = t.start::<_, BlockingWait>(vec![(e1, Box::new(journaller) as BoxedHandler<TestSlot, ()>),
+    ///                                               (e2, Box::new(business_logic))]).unwrap();
+    ///
+    ///t.write(Slot::new());
+    ///let results = pipeline.shutdown();   // vec![Ok((journaller, 1)), Ok((business_logic, 1))]
+    ///```
+    pub fn start<E, W>(&mut self, handlers: Vec<(usize, BoxedHandler<T, E>)>) -> Result<Pipeline<T, E>, TurbineError>
+    where T: 'static, E: Send + 'static, W: WaitStrategy + 'static {
+        self.start_pinned::<E, W>(handlers, &AffinityConfig::new())
+    }
+
+    /// Like `start()`, but each thread is pinned to the cores `affinity` maps its EP's
+    /// token to (if any).  See `AffinityConfig`.
+    pub fn start_pinned<E, W>(&mut self, handlers: Vec<(usize, BoxedHandler<T, E>)>,
+                              affinity: &AffinityConfig) -> Result<Pipeline<T, E>, TurbineError>
+    where T: 'static, E: Send + 'static, W: WaitStrategy + 'static {
+        try!(self.validate_and_finalize());
+
+        for (i, &(token, _)) in handlers.iter().enumerate() {
+            match self.pools.get(token) {
+                None => return Err(TurbineError::UnknownProcessor(token)),
+                Some(&Some(_)) => return Err(TurbineError::WorkerPoolToken(token)),
+                Some(&None) => {}
+            }
+            if self.taken[token] == true || handlers[..i].iter().any(|&(t, _)| t == token) {
+                return Err(TurbineError::AlreadyTaken(token));
+            }
+        }
+
+        let mut processors = Vec::with_capacity(handlers.len());
+        for (token, handler) in handlers.into_iter() {
+            processors.push((try!(self.ep_finalize(token)), handler));
+        }
+        builder::spawn_all::<T, E, W>(self, processors, affinity, &None)
+    }
+
+    /// Obtain a cloneable MultiProducer handle for writing from other threads.
+    ///
+    /// Only available if this Turbine was created with `new_multi_producer()`
+    /// (`TurbineError::NotMultiProducer` otherwise), and only after the graph has been
+    /// finalized (e.g. `ep_finalize` has been called), since the producer needs the
+    /// final set of cursors to gate on (`TurbineError::NotFinalized` otherwise).
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new_multi_producer(1024);
+    ///let e1 = t.ep_new().unwrap();
+    ///let event_processor = t.ep_finalize(e1).unwrap();
+    ///
+    ///let producer = t.producer().unwrap();
+    ///thread::spawn(move|| {
+    ///    producer.write(Slot::new());
+    ///});
+    ///```
+    pub fn producer(&self) -> Result<MultiProducer<T>, TurbineError> {
+        match self.producer {
+            Some(ref p) => Ok(p.clone()),
+            None if self.available.is_none() => Err(TurbineError::NotMultiProducer),
+            None => Err(TurbineError::NotFinalized)
+        }
+    }
+
+    /// Attach a new consumer to the running Turbine.
+    ///
+    /// Unlike EventProcessors, which are fixed once the graph is finalized, Subscribers
+    /// may be attached and detached at any time.  The Subscriber starts at the producer's
+    /// current position, so it sees every event written from now on, and the producer
+    /// gates on it until it is detached (or dropped).  If the graph has EPs marked with
+    /// `ep_mutable()`, the Subscriber only sees an event once they have all released it.
+    /// See `Subscriber`, and `subscribe_from()` to replay earlier events.
+    ///
+    /// This method returns a Result.  Failure occurs if the graph has not been finalized
+    /// yet (`TurbineError::NotFinalized`).
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut subscriber = t.subscribe().unwrap();
+    ///t.write(x);
+    ///subscriber.poll(&mut auditor).unwrap();   // sees x
+    ///subscriber.detach();
+    ///```
+    pub fn subscribe(&mut self) -> Result<Subscriber<T>, TurbineError> {
+        self.subscribe_from(StartAt::Latest)
+    }
+
+    /// Attach a new consumer to the running Turbine, starting at `start`.
+    ///
+    /// Like `subscribe()`, but the Subscriber may start with events which were written
+    /// before it was attached.  `StartAt::Oldest` replays everything still in the ring
+    /// (up to one ring's worth of events), giving e.g. a freshly started monitor some
+    /// context.  In multi-producer mode, other producers may be overwriting released
+    /// slots while the Subscriber is attached, so only events which another consumer has
+    /// not handled yet can be replayed.
+    ///
+    /// This method returns a Result.  Failure occurs if the graph has not been finalized
+    /// yet (`TurbineError::NotFinalized`), or if an explicit `StartAt::Sequence` is no
+    /// longer (or not yet) available (`TurbineError::SequenceUnavailable`).
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut monitor = t.subscribe_from(StartAt::Oldest).unwrap();
+    ///monitor.poll(&mut dashboard).unwrap();   // sees the backlog, then new events
+    ///```
+    pub fn subscribe_from(&mut self, start: StartAt) -> Result<Subscriber<T>, TurbineError> {
         if self.finalized == false {
-            self.finalize_graph();
+            return Err(TurbineError::NotFinalized);
+        }
+
+        // A Subscriber reads behind the mutable EPs, so it never sees a slot being modified
+        let mut deps: Vec<usize> = self.mutable.iter().enumerate()
+                                       .filter(|&(_, &mutable)| mutable)
+                                       .map(|(token, _)| token + 1)
+                                       .collect();
+        if deps.is_empty() {
+            deps.push(0);
+        }
+
+        let subscriber = try!(Subscriber::new(self.ring.clone(), self.cursors.clone(), self.stopped.clone(), deps,
+                                              self.available.clone(), self.signal.clone(), self.dynamic.clone(),
+                                              start, self.producer.is_some()));
+
+        // The cached gating positions predate the subscriber, so force a refresh
+        match self.producer {
+            Some(ref producer) => producer.reset_gating(),
+            None => self.until = self.current_pos & self.mask
         }
+        Ok(subscriber)
+    }
 
-        EventProcessor::<T>::new(self.ring.clone(), self.graph.clone(), self.cursors.clone(), token)
+    /// Validate and finalize the dependency graph, unless that has already been done
+    fn validate_and_finalize(&mut self) -> Result<(), TurbineError> {
+        if self.finalized == false {
+            try!(graph::validate(&self.epb, &self.mutable));
+            self.finalize_graph();
+        }
+        Ok(())
     }
 
     /// Finalize the dependency graph.
@@ -297,25 +736,187 @@ impl<T: Slot> Turbine<T> {
     /// In practice, code will look up the dependencies in the graph, then use the
     /// retrieved values to read specific cursor values.
     ///
-    /// The first cursor is the "root" cursor and belongs to the writer.
+    /// The first cursor is the "root" cursor and belongs to the writer.  EP tokens
+    /// are therefore shifted by one when converted into cursor IDs, and an EP
+    /// without dependencies depends on the root cursor.
     ///
     fn finalize_graph(&mut self) {
         let mut eps: Vec<Vec<usize>> = Vec::with_capacity(self.epb.len());
         let mut cursors: Vec<Padded64> = Vec::with_capacity(self.epb.len() + 1);
+        let mut stopped: Vec<AtomicBool> = Vec::with_capacity(self.epb.len() + 1);
 
         // Add the root cursor
         cursors.push(Padded64::new(0));
+        stopped.push(AtomicBool::new(false));
 
         for node in self.epb.iter() {
-            let deps: Vec<usize> = node.clone();
+            let deps: Vec<usize> = match node.len() {
+                0 => vec![0],
+                _ => node.iter().map(|dep| dep + 1).collect()
+            };
             eps.push(deps);
             cursors.push(Padded64::new(0));
+            stopped.push(AtomicBool::new(false));
         }
 
         self.graph = Arc::new(eps);
         self.cursors = Arc::new(cursors);
+        self.stopped = Arc::new(stopped);
         drop(&self.epb);
         self.finalized = true;
+
+        if self.track_latency {
+            self.latency = Some(Arc::new(Latency::new(self.size, self.epb.len())));
+        }
+
+        if let Some(ref available) = self.available {
+            self.producer = Some(MultiProducer::new(self.ring.clone(), self.cursors.clone(),
+                                                   available.clone(), self.dynamic.clone(), self.signal.clone(),
+                                                   self.backpressure.clone(), self.latency.clone()));
+        }
+    }
+
+    /// Halt all EventProcessors.
+    ///
+    /// This raises an alert flag which is checked by every WaitStrategy.  Each
+    /// EventProcessor finishes processing the data which has already been published
+    /// (waiting for its dependencies to drain first), then returns from `start()`
+    /// with its final cursor position.
+    ///
+    /// Data should not be written after halting; there is nobody left to read it.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///let e1 = t.ep_new().unwrap();
+    ///let event_processor = t.ep_finalize(e1).unwrap();
+    ///
+    ///thread::spawn(move|| {
+    ///    event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+    ///        Ok(())
+    ///    });
+    ///});
+    ///
+    ///t.write(Slot::new());
+    ///t.halt();
+    ///let cursors = t.join();     // vec![1]
+    ///```
+    pub fn halt(&self) {
+        self.signal.alert();
+    }
+
+    /// Obtain a cloneable handle which can halt this Turbine from another thread.
+    pub fn halt_handle(&self) -> HaltHandle {
+        HaltHandle::new(self.signal.clone())
+    }
+
+    /// Call `callback` whenever a write has waited longer than `threshold` for a free slot.
+    ///
+    /// A write which cannot proceed because the ring is full busy-spins until the
+    /// slowest EP releases a slot.  The callback is invoked (at most once per write) on
+    /// the writing thread, while it is still waiting, with the sequence being written and
+    /// the consumer holding it back (an EP's token, or a Subscriber).  Use it to log or alert on slow consumers;
+    /// it should return quickly since it delays the write.
+    ///
+    /// Counters for all waiting writes are available from `stats_handle()` regardless.
+    /// This method returns a Result.  Failure occurs if the graph has been `finalized`
+    /// (`TurbineError::AlreadyFinalized`).
+    ///
+    ///# Example
+    ///
+    ///```
+    ///t.on_stall(Duration::from_millis(100), |stall: &Stall| {
+    ///    println!("Writer stuck for {:?} behind {:?}", stall.duration, stall.bottleneck);
+    ///}).unwrap();
+    ///```
+    pub fn on_stall<F>(&mut self, threshold: Duration, callback: F) -> Result<(), TurbineError>
+    where F: Fn(&Stall) + Send + Sync + 'static {
+        if self.finalized == true {
+            return Err(TurbineError::AlreadyFinalized);
+        }
+
+        // Nothing else holds the counters until the graph is finalized
+        Arc::get_mut(&mut self.backpressure).unwrap().set_stall_callback(threshold, Box::new(callback));
+        Ok(())
+    }
+
+    /// Record the latency between publishing each event and each EP handling it.
+    ///
+    /// Every published sequence is stamped with the current time, and each EP (or
+    /// WorkerPool) records how long it took to get to it in a histogram.  The histograms
+    /// are reported in `ProcessorStats::latency`, so latency percentiles are available
+    /// for any pipeline without storing timestamps in the Slots.  Tracking costs a clock
+    /// read per publish and per batch, so it is off by default.
+    ///
+    /// Latency is measured up to the point where the EP's closure or handler returns.
+    /// This method returns a Result.  Failure occurs if the graph has been `finalized`
+    /// (`TurbineError::AlreadyFinalized`).
+    ///
+    ///# Example
+    ///
+    ///```
+    ///t.track_latency().unwrap();
+    ///// ... finalize, start EPs and write ... //
+    ///
+    ///let stats = t.stats_handle().unwrap().snapshot();
+    ///let latency = stats.processors[0].latency.as_ref().unwrap();
+    ///println!("p99: {}ns", latency.percentile(99.0));
+    ///```
+    pub fn track_latency(&mut self) -> Result<(), TurbineError> {
+        match self.finalized {
+            true => Err(TurbineError::AlreadyFinalized),
+            false => {
+                self.track_latency = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Obtain a cloneable handle which can read TurbineStats from any thread.
+    ///
+    /// The stats report the producer's position, each EP's position and lag, and how
+    /// much of the ring is in use, which is useful for monitoring and for spotting slow
+    /// EPs.  Only available once the graph has been finalized (`TurbineError::NotFinalized`
+    /// otherwise).
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let stats = t.stats_handle().unwrap();
+    ///thread::spawn(move|| {
+    ///    loop {
+    ///        for ep in stats.snapshot().processors.iter() {
+    ///            println!("{}: {} behind", ep.name, ep.lag);
+    ///        }
+    ///        thread::sleep(Duration::from_secs(1));
+    ///    }
+    ///});
+    ///```
+    pub fn stats_handle(&self) -> Result<StatsHandle, TurbineError> {
+        match self.finalized {
+            false => Err(TurbineError::NotFinalized),
+            true => Ok(StatsHandle::new(self.cursors.clone(), self.stopped.clone(),
+                                        Arc::new(self.names.clone()), self.dynamic.clone(),
+                                        self.backpressure.clone(), self.latency.clone(), self.size))
+        }
+    }
+
+    /// Wait for every EventProcessor in the graph to stop, returning their final cursors.
+    ///
+    /// The returned vector is indexed by EP token.  An EP stops when its closure returns
+    /// Err(()), or after it has drained the ring following a `halt()`.  This method
+    /// blocks until *all* EPs in the graph have stopped, so every EP that was added with
+    /// `ep_new()` must actually be started.  Returns an empty vector if the graph has not
+    /// been finalized.
+    ///
+    /// This only reports the cursors.  The threads of EPs run with `start()` (or a
+    /// `PipelineBuilder`) belong to the returned `Pipeline`, whose `join()` also hands
+    /// the handlers back.
+    pub fn join(&self) -> Vec<u64> {
+        let stopped = self.stopped.clone();
+        self.signal.block_until(move|| stopped.iter().skip(1).all(|s| s.load(Ordering::SeqCst)));
+        self.cursors.iter().skip(1).map(|c| c.load()).collect()
     }
 
     /// Write data isizeo Turbine
@@ -323,6 +924,8 @@ impl<T: Slot> Turbine<T> {
     /// All writes in Turbine go through the thread that owns the original Turbine
     /// object.  This makes Turbine a Single Producer Multi Consumer queue (of sorts).
     /// By being Single Producer, the writing code is much simpler to make lock-free.
+    /// If this Turbine was created with `new_multi_producer()`, the write is routed
+    /// through the shared `MultiProducer` instead.
     ///
     /// The write method maisizeains an isizeernal `until` value which allows it to
     /// minimize reads on the EP Atomics, which reduces isizeer-core communication.
@@ -335,7 +938,7 @@ impl<T: Slot> Turbine<T> {
     ///  let mut t: Turbine<TestSlot> = Turbine::new(1024);
     ///  let e1 = t.ep_new().unwrap();
     ///
-    ///  let event_processor = t.ep_finalize(e1);
+    ///  let event_processor = t.ep_finalize(e1).unwrap();
     ///
     ///  let d: TestSlot = Slot::new();	// Instantiate a new TestSlot
     ///  d.value = 19;					    // Our TestSlot has a public `value` variable
@@ -344,80 +947,303 @@ impl<T: Slot> Turbine<T> {
     ///```
     ///
     pub fn write(&mut self, data: T) {
+        let mut slot = self.claim();
+        *slot = data;
+        slot.publish();
+        debug!("Write complete.")
+    }
 
-        // Busy spin
-        loop {
-            //debug!("Spin...");
-            match self.can_write() {
-                true => break,
-                false => {}
-            }
+    /// Claim the next slot in the RingBuffer for writing in place.
+    ///
+    /// Rather than moving a whole Slot into the buffer like `write()`, `claim()`
+    /// returns a `Claim` which dereferences to the preallocated slot itself.  The slot
+    /// is published when the Claim is dropped (or `publish()` is called on it).  This
+    /// avoids a large copy per event when Slots are big.
+    ///
+    /// Like `write()`, this method will busy-spin until a free slot is open.  The
+    /// slot still contains the data written to it on the previous lap of the ring.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///fn test_claim() {
+    ///  let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///  let e1 = t.ep_new().unwrap();
+    ///
+    ///  let event_processor = t.ep_finalize(e1).unwrap();
+    ///
+    ///  let mut slot = t.claim();
+    ///  slot.value = 19;
+    ///  slot.publish();
+    ///}
+    ///```
+    ///
+    pub fn claim(&mut self) -> Claim<T> {
+        if self.producer.is_some() {
+            return self.producer.as_ref().unwrap().claim();
         }
 
-        let write_pos = self.current_pos & self.mask;
-        debug!("current_pos is {}, writing to {}", self.current_pos, write_pos);
-        unsafe {
-            self.ring.write(write_pos as usize, data);
+        // Busy spin
+        if !self.can_write() {
+            let mut waiting = Waiting::new(self.backpressure.clone(), self.current_pos, self.cursors.as_slice(), &self.dynamic);
+            while !self.can_write() {
+                waiting.spin();
+            }
         }
 
+        let sequence = self.current_pos;
+        let write_pos = sequence & self.mask;
+        debug!("current_pos is {}, claiming {}", sequence, write_pos);
         self.current_pos += 1;
-        self.cursors.as_slice()[0].store(self.current_pos);
-        debug!("Write complete.")
 
+        let slot = unsafe { self.ring.get_mut(write_pos as usize) };
+        Claim::new(slot, sequence, Publisher::Cursor(&self.cursors.as_slice()[0]), &*self.signal,
+                   self.latency.as_ref().map(|l| &**l))
     }
 
-    /// Check if there is a free slot in the RingBuffer
+    /// Attempt to write data into Turbine without waiting.
     ///
-    /// This method determines if there is a free slot which the writer can use.
-    /// To do this, it must find the minimum cursor value and mask that against
-    /// the size of the RingBuffer.  Once a suitable "until" value has been found,
-    /// this is cached to help reduce loading Atomics and invalidating caches.
+    /// `write()` busy-spins until a slot is free, which can stall a producer that
+    /// has other duties while EventProcessors lag behind.  `try_write()` instead
+    /// returns immediately: if the ring is full, the data is handed back inside the
+    /// Err so that the producer can drop it, retry later or divert it elsewhere.
     ///
-    /// Returns true if there is a free slot, false otherwise.
-    fn can_write(&mut self) -> bool {
-        debug!("{} == {} ({} & {})  -- {}", self.until, self.current_pos & self.mask, self.current_pos, self.mask, self.until == (self.current_pos & self.mask));
-
-        if self.until == (self.current_pos & self.mask) {
-            debug!("*****");
-
-            let mut min_cursor = 18446744073709551615;
-            for v in self.cursors.iter().skip(1) {
-                debug!("CURSOR: {}", v.load());
-                //let diff = self.current_pos - v.load();
-                min_cursor = min(min_cursor, v.load());
-
-                if self.current_pos - min_cursor >= self.size as u64 {
-                    debug!("Not writeable!  {} - {} == {}, which is >= {}", self.current_pos, min_cursor, (self.current_pos - min_cursor), self.size);
-                    return false;
-                }
-            }
+    ///# Example
+    ///
+    ///```
+    ///fn test_try_write() {
+    ///  let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///  let e1 = t.ep_new().unwrap();
+    ///
+    ///  let event_processor = t.ep_finalize(e1).unwrap();
+    ///
+    ///  let d: TestSlot = Slot::new();
+    ///  if let Err(d) = t.try_write(d) {
+    ///    overflow.push(d);            // Ring is full, keep it for later
+    ///  }
+    ///}
+    ///```
+    ///
+    pub fn try_write(&mut self, data: T) -> Result<(), T> {
+        match self.try_claim() {
+            Ok(mut slot) => {
+                *slot = data;
+                slot.publish();
+                Ok(())
+            },
+            Err(_) => Err(data)
+        }
+    }
 
-            self.until = min_cursor & self.mask;
+    /// Attempt to claim the next slot for writing in place, without waiting.
+    ///
+    /// This is the non-blocking version of `claim()`.  Returns `TurbineError::RingFull`
+    /// if there is no free slot, or `TurbineError::Halted` if the Turbine has been
+    /// halted (no EventProcessor will ever read the slot).
+    pub fn try_claim(&mut self) -> Result<Claim<T>, TurbineError> {
+        if self.producer.is_some() {
+            return self.producer.as_ref().unwrap().try_claim();
+        }
 
-            debug!("current_pos: {}, min_cursor: {}, new until: {}", self.current_pos, min_cursor, self.until);
-            debug!("current_pos & mask: {}, min_cursor & mask: {}", (self.current_pos & self.mask), (min_cursor & self.mask));
+        if self.signal.is_alerted() {
+            return Err(TurbineError::Halted);
         }
 
-        true
+        match self.can_write() {
+            true => Ok(self.claim()),
+            false => Err(TurbineError::RingFull)
+        }
     }
-}
+
+    /// The number of slots which could currently be written without waiting.
+    ///
+    /// This is computed from the slowest EventProcessor's cursor, so it is only a
+    /// snapshot: EPs may release more slots at any moment.  Before the graph has been
+    /// finalized, the entire ring is reported as free.
+    pub fn remaining_capacity(&self) -> usize {
+        if self.producer.is_some() {
+            return self.producer.as_ref().unwrap().remaining_capacity();
+        }
+        if self.finalized == false {
+            return self.size;
+        }
+
+        let min_cursor = minimum_cursor(self.cursors.as_slice(), self.current_pos);
+        let min_cursor = self.dynamic.minimum(min_cursor, None);
+        self.size - min(self.current_pos - min_cursor, self.size as u64) as usize
+    }
+
+    /// Claim up to `n` contiguous slots in the RingBuffer for writing in place.
+    ///
+    /// This is the batched version of `claim()`: the returned `BatchClaim` gives
+    /// access to `min(n, ring_size)` slots, and publishes all of them with a single
+    /// cursor update when dropped.  Producers which receive bursts of events (e.g.
+    /// a decoded packet containing many messages) avoid a capacity check and a
+    /// cursor store per event.
+    ///
+    /// This method will busy-spin until all of the slots are free.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///fn test_claim_batch() {
+    ///  let mut t: Turbine<TestSlot> = Turbine::new(1024);
+    ///  let e1 = t.ep_new().unwrap();
+    ///
+    ///  let event_processor = t.ep_finalize(e1).unwrap();
+    ///
+    ///  let mut batch = t.claim_batch(40);
+    ///  for i in 0..batch.len() {
+    ///    batch[i].value = i as i32;
+    ///  }
+    ///  batch.publish();
+    ///}
+    ///```
+    ///
+    pub fn claim_batch(&mut self, n: usize) -> BatchClaim<T> {
+        if self.producer.is_some() {
+            return self.producer.as_ref().unwrap().claim_batch(n);
+        }
+
+        let n = min(n, self.size);
+
+        // Busy spin
+        if !self.can_write_batch(n as u64) {
+            let mut waiting = Waiting::new(self.backpressure.clone(), self.current_pos, self.cursors.as_slice(), &self.dynamic);
+            while !self.can_write_batch(n as u64) {
+                waiting.spin();
+            }
+        }
+
+        let sequence = self.current_pos;
+        debug!("current_pos is {}, claiming {} slots", sequence, n);
+        self.current_pos += n as u64;
+
+        let (first, second) = unsafe { self.ring.get_wrapping_mut((sequence & self.mask) as usize, n) };
+        BatchClaim::new(first, second, sequence, Publisher::Cursor(&self.cursors.as_slice()[0]), &*self.signal,
+                        self.latency.as_ref().map(|l| &**l))
+    }
+
+    /// Check if there are `n` free slots in the RingBuffer
+    ///
+    /// Unlike `can_write()`, this always loads every EP cursor.  The cached `until`
+    /// value is refreshed as well, since a batch may jump over the old one.
+    fn can_write_batch(&mut self, n: u64) -> bool {
+        let min_cursor = minimum_cursor(self.cursors.as_slice(), self.current_pos);
+        let min_cursor = self.dynamic.minimum(min_cursor, None);
+
+        if self.current_pos + n - min_cursor > self.size as u64 {
+            debug!("Batch not writeable!  {} + {} - {} > {}", self.current_pos, n, min_cursor, self.size);
+            return false;
+        }
+
+        self.until = min_cursor & self.mask;
+        true
+    }
+
+    /// Check if there is a free slot in the RingBuffer
+    ///
+    /// This method determines if there is a free slot which the writer can use.
+    /// To do this, it must find the minimum cursor value and mask that against
+    /// the size of the RingBuffer.  Once a suitable "until" value has been found,
+    /// this is cached to help reduce loading Atomics and invalidating caches.
+    ///
+    /// Returns true if there is a free slot, false otherwise.
+    fn can_write(&mut self) -> bool {
+        debug!("{} == {} ({} & {})  -- {}", self.until, self.current_pos & self.mask, self.current_pos, self.mask, self.until == (self.current_pos & self.mask));
+
+        if self.until == (self.current_pos & self.mask) {
+            debug!("*****");
+
+            let mut min_cursor = 18446744073709551615;
+            for v in self.cursors.iter().skip(1) {
+                debug!("CURSOR: {}", v.load());
+                //let diff = self.current_pos - v.load();
+                min_cursor = min(min_cursor, v.load());
+
+                if self.current_pos - min_cursor >= self.size as u64 {
+                    debug!("Not writeable!  {} - {} == {}, which is >= {}", self.current_pos, min_cursor, (self.current_pos - min_cursor), self.size);
+                    return false;
+                }
+            }
+
+            // Subscribers attached since the graph was finalized
+            min_cursor = self.dynamic.minimum(min_cursor, None);
+            if min_cursor <= self.current_pos && self.current_pos - min_cursor >= self.size as u64 {
+                debug!("Not writeable!  Held back by a subscriber at {}", min_cursor);
+                return false;
+            }
+
+            self.until = min_cursor & self.mask;
+
+            debug!("current_pos: {}, min_cursor: {}, new until: {}", self.current_pos, min_cursor, self.until);
+            debug!("current_pos & mask: {}, min_cursor & mask: {}", (self.current_pos & self.mask), (min_cursor & self.mask));
+        }
+
+        true
+    }
+}
+
+impl<T: Slot> SinkProducer<T> for Turbine<T> {
+    fn try_claim_slot(&mut self) -> Result<Claim<T>, TurbineError> {
+        self.try_claim()
+    }
+
+    fn signal(&self) -> &WaitSignal {
+        &self.signal
+    }
+
+    fn waiting(&self) -> Waiting {
+        Waiting::new(self.backpressure.clone(), self.current_pos, self.cursors.as_slice(), &self.dynamic)
+    }
+
+    fn parked_send(&mut self) -> &mut Option<Waiting> {
+        &mut self.parked_send
+    }
+}
+
+/// Turbine can be used as an async producer, see the `Sink` impl of `MultiProducer`.
+///
+/// While the ring is full, the task is parked rather than busy-spinning like `write()`.
+impl<T: Slot> Sink for Turbine<T> {
+    type SinkItem = T;
+    type SinkError = TurbineError;
+
+    fn start_send(&mut self, item: T) -> StartSend<T, TurbineError> {
+        if self.producer.is_some() {
+            return self.producer.as_mut().unwrap().start_send(item);
+        }
+        sequencer::start_send(self, item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), TurbineError> {
+        Ok(Async::Ready(()))
+    }
+}
 
 #[cfg(test)]
 mod test {
-    use libc::funcs::posix88::unistd::usleep;
+    use libc::usleep;
     use rand::{Rng, thread_rng};
     use std::fs::File;
     use std::io::Write;
     use std::path::Path;
+    use std::sync::Arc;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::mpsc::{channel, Sender, Receiver};
     use std::{thread, u64};
+    use std::cell::Cell;
     use std::time::Duration;
     use time::precise_time_ns;
 
     use Turbine;
     use Slot;
-    use waitstrategy::BusyWait;
+    use {GraphError, TurbineError, EventHandler, ExceptionHandler, ExceptionAction, HaltOnException, Stall, Bottleneck};
+    use {PollState, StartAt, SubscriberStats};
+    use std::any::Any;
+    use futures::{Stream, Sink, Future};
+    use futures::stream;
+    use waitstrategy::{BusyWait, BlockingWait};
     
     #[derive(Copy, Clone)]
     struct TestSlot {
@@ -495,12 +1321,12 @@ mod test {
         let _ = t.ep_depends(e4, e3);
         let _ = t.ep_depends(e3, e2);
 
-        t.ep_finalize(e1);
-        t.ep_finalize(e2);
-        t.ep_finalize(e3);
-        t.ep_finalize(e4);
-        t.ep_finalize(e5);
-        t.ep_finalize(e6);
+        t.ep_finalize(e1).unwrap();
+        t.ep_finalize(e2).unwrap();
+        t.ep_finalize(e3).unwrap();
+        t.ep_finalize(e4).unwrap();
+        t.ep_finalize(e5).unwrap();
+        t.ep_finalize(e6).unwrap();
     }
 
     #[test]
@@ -520,27 +1346,117 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new();
         assert!(e1.is_ok() == true);
+        let e1 = e1.unwrap();
 
-        let _event_processor = t.ep_finalize(e1.unwrap());
-        let _event_processor2 = t.ep_finalize(e1.unwrap());
+        let event_processor = t.ep_finalize(e1);
+        assert!(event_processor.is_ok() == true);
+
+        // Two EPs would share a single cursor
+        let event_processor2 = t.ep_finalize(e1);
+        assert!(event_processor2.err() == Some(TurbineError::AlreadyTaken(e1)));
 
         let e2 = t.ep_new();
         assert!(e2.is_err() == true);
     }
 
+    #[test]
+    fn test_finalize_invalid_graph() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        let e3 = t.ep_new().unwrap();
+
+        let _ = t.ep_depends(e1, e3);
+        let _ = t.ep_depends(e2, e1);
+        let _ = t.ep_depends(e3, e2);
+
+        match t.ep_finalize(e1) {
+            Err(TurbineError::InvalidDependency(GraphError::Cycle(tokens))) => assert!(tokens == vec![e1, e3, e2]),
+            _ => panic!("Cycle was not detected")
+        }
+
+        // The graph is left unfinalized
+        assert!(t.ep_new().is_ok() == true);
+    }
+
+    #[test]
+    fn test_finalize_unknown_token() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let _ = t.ep_depends(e1, 7);
+        assert!(t.ep_finalize(e1).err() == Some(TurbineError::InvalidDependency(
+            GraphError::UnknownDependency { processor: e1, dependency: 7 })));
+
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        assert!(t.ep_finalize(e1 + 1).err() == Some(TurbineError::UnknownProcessor(e1 + 1)));
+        assert!(t.ep_finalize(e1).is_ok() == true);
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        assert!(t.ep_depends(e1 + 1, e1) == Err(TurbineError::UnknownProcessor(e1 + 1)));
+        assert!(t.producer().err() == Some(TurbineError::NotMultiProducer));
+
+        let _event_processor = t.ep_finalize(e1).unwrap();
+        assert!(t.ep_new() == Err(TurbineError::AlreadyFinalized));
+        assert!(t.ep_depends(e1, e1) == Err(TurbineError::AlreadyFinalized));
+
+        let t: Turbine<TestSlot> = Turbine::new_multi_producer(1024);
+        assert!(t.producer().err() == Some(TurbineError::NotFinalized));
+    }
+
+    #[test]
+    fn test_handler_error_propagates() {
+        #[derive(Debug, PartialEq)]
+        enum HandlerError {
+            BadValue(i32)
+        }
+
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let future = thread::spawn(move|| {
+            event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(), HandlerError> {
+                for x in data.iter() {
+                    if x.value < 0 {
+                        return Err(HandlerError::BadValue(x.value));
+                    }
+                }
+                Ok(())
+            })
+        });
+
+        for i in 0..10 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+        t.write(Slot::new());   // value is -1
+
+        assert!(future.join().unwrap() == Err(HandlerError::BadValue(-1)));
+        assert!(t.halt_handle().is_halted() == false);
+        assert!(t.join() == vec![11]);
+    }
+
     #[test]
     fn test_send_task() {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new();
         assert!(e1.is_ok() == true);
+        let e1 = e1.unwrap();
 
         let e2 = t.ep_new();
         assert!(e2.is_ok() == true);
+        let e2 = e2.unwrap();
 
-        let _ = t.ep_depends(e2.unwrap(), e1.unwrap());
+        let _ = t.ep_depends(e2, e1);
 
-        let ep1 = t.ep_finalize(e1.unwrap());
-        let ep2 = t.ep_finalize(e2.unwrap());
+        let ep1 = t.ep_finalize(e1);
+        let ep2 = t.ep_finalize(e2);
 
         thread::spawn(move || {
             let _a = ep1;
@@ -556,7 +1472,7 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let _event_processor = t.ep_finalize(e1);
+        let _event_processor = t.ep_finalize(e1).unwrap();
 
         assert!(t.current_pos == 0);
         t.write(Slot::new());
@@ -570,7 +1486,7 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let _event_processor = t.ep_finalize(e1);
+        let _event_processor = t.ep_finalize(e1).unwrap();
 
         assert!(t.current_pos == 0);
 
@@ -589,7 +1505,7 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let _event_processor = t.ep_finalize(e1);
+        let _event_processor = t.ep_finalize(e1).unwrap();
 
         assert!(t.current_pos == 0);
 
@@ -610,7 +1526,7 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let _event_processor = t.ep_finalize(e1);
+        let _event_processor = t.ep_finalize(e1).unwrap();
 
         assert!(t.current_pos == 0);
 
@@ -637,11 +1553,11 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
         let mut _future = thread::spawn(move || {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 //debug!("data[0].value: {}", data[0].value);
                 assert!(data.len() == 1);
                 assert!(data[0].value == 19);
@@ -668,12 +1584,12 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
         let _future = thread::spawn(move|| {
             let counter = AtomicUsize::new(0);
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut last = 0;
                 //debug!("EP::data.len: {}", data.len());
                 let mut previous = 0;
@@ -715,11 +1631,11 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
         let _future = thread::spawn(move|| {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut counter = 0;
                 let mut last = -1;
                 for x in data.iter() {
@@ -756,12 +1672,12 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
 
         let _future = thread::spawn(move|| {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut counter = 0;
                 let mut last = -1;
                 //debug!("EP::data.len: {}", data.len());
@@ -806,12 +1722,12 @@ mod test {
         let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
 
         let _future = thread::spawn(move|| {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut rng = thread_rng();
                 let mut counter = 0;
                 let mut last = -1;
@@ -861,11 +1777,11 @@ mod test {
         let e1 = t.ep_new().unwrap();
         let e2 = t.ep_new().unwrap();
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
         let _future = thread::spawn(move|| {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut counter = 0;
                 let mut last = -1;
                 for x in data.iter() {
@@ -886,11 +1802,11 @@ mod test {
             let _ = tx.send(1);
         });
 
-        let event_processor2 = t.ep_finalize(e2);
+        let event_processor2 = t.ep_finalize(e2).unwrap();
         let (tx2, rx2): (Sender<isize>, Receiver<isize>) = channel();
 
         let _future = thread::spawn(move|| {
-            event_processor2.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor2.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut counter = 0;
                 let mut last = -1;
                 for x in data.iter() {
@@ -931,11 +1847,11 @@ mod test {
 
         let _ = t.ep_depends(e2, e1);
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
         let _future = thread::spawn(move|| {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut counter = 0;
                 let mut last = -1;
                 for x in data.iter() {
@@ -956,11 +1872,11 @@ mod test {
             let _ = tx.send(1);
         });
 
-        let event_processor2 = t.ep_finalize(e2);
+        let event_processor2 = t.ep_finalize(e2).unwrap();
         let (tx2, rx2): (Sender<isize>, Receiver<isize>) = channel();
 
         let _future = thread::spawn(move|| {
-            event_processor2.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            let _ = event_processor2.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
                 let mut counter = 0;
                 let mut last = -1;
                 for x in data.iter() {
@@ -995,90 +1911,1330 @@ mod test {
     }
 
     #[test]
-    fn bench_chan_10m() {
-
-        let (tx_bench, rx_bench): (Sender<isize>, Receiver<isize>) = channel();
-
-
-        let _future = thread::spawn(move|| {
-            for _ in 0..10000000 {
-                let _ = tx_bench.send(1);
-            }
-        });
-
-        let start = precise_time_ns();
-        let mut counter = 0;
-        for _ in 0..10000000 {
-            counter += rx_bench.recv().ok().unwrap();
-        }
-        let end = precise_time_ns();
-        assert!(counter > 0);
+    fn test_multi_producer_requires_finalize() {
+        let mut t: Turbine<TestSlot> = Turbine::new_multi_producer(1024);
+        let e1 = t.ep_new().unwrap();
+        assert!(t.producer().is_err() == true);
 
-        let _ = _future.join();
+        let _event_processor = t.ep_finalize(e1).unwrap();
+        assert!(t.producer().is_ok() == true);
 
-        error!("Channel: Total time: {}", (end-start) as f32 / 1000000f32);
-        error!("Channel: ops/s: {}", 10000000f32 / ((end-start) as f32 / 1000000f32 / 1000f32));
+        let mut t2: Turbine<TestSlot> = Turbine::new(1024);
+        let e2 = t2.ep_new().unwrap();
+        let _event_processor2 = t2.ep_finalize(e2).unwrap();
+        assert!(t2.producer().is_err() == true);
     }
 
     #[test]
-    fn bench_turbine_10m() {
-        let mut t: Turbine<TestSlot> = Turbine::new(1048576);
+    fn test_multi_producer_write_read() {
+        let mut t: Turbine<TestSlot> = Turbine::new_multi_producer(1024);
         let e1 = t.ep_new().unwrap();
 
-        let event_processor = t.ep_finalize(e1);
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
 
         let _future = thread::spawn(move|| {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
-                let mut counter = 0;
-                for _ in data.iter() {
-                    counter += data[0].value;
+            let mut counter = 0;
+            // Values are written as `producer * 10000 + i`, so each producer's
+            // writes must be seen in order even though they are interleaved
+            let mut last = vec![-1, -1, -1, -1];
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+                for x in data.iter() {
+                    let producer = (x.value / 10000) as usize;
+                    let value = x.value % 10000;
+                    assert!(last[producer] + 1 == value);
+                    last[producer] = value;
+                    counter += 1;
                 }
 
-                if counter == 10000000 {
+                if counter >= 20000 {
                     return Err(());
                 } else {
                     return Ok(());
                 }
-
             });
             let _ = tx.send(1);
         });
 
-        let start = precise_time_ns();
-        for _ in 0..10000000 {
-            let mut s: TestSlot = Slot::new();
-            s.value = 1;
-            t.write(s);
+        let mut futures = Vec::with_capacity(4);
+        for p in 0..4 {
+            let producer = t.producer().unwrap();
+            futures.push(thread::spawn(move|| {
+                for i in 0..5000 {
+                    let mut x: TestSlot = Slot::new();
+                    x.value = p * 10000 + i;
+                    producer.write(x);
+                }
+            }));
         }
 
-        let _ = rx.recv();
-        let end = precise_time_ns();
+        for f in futures {
+            let _ = f.join();
+        }
+        if rx.recv().is_err() == true {panic!()}
+    }
+
+    #[test]
+    fn test_blocking_wait_dependency() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        let _ = t.ep_depends(e2, e1);
 
+        let mut futures = Vec::with_capacity(2);
+        for token in vec![e1, e2] {
+            let event_processor = t.ep_finalize(token).unwrap();
+            futures.push(thread::spawn(move|| {
+                let mut counter = 0;
+                let mut last = -1;
+                let _ = event_processor.start::<_, _, BlockingWait>(|data: &[TestSlot]| -> Result<(),()> {
+                    for x in data.iter() {
+                        assert!(last + 1 == x.value);
+                        counter += 1;
+                        last = x.value;
+                    }
+
+                    if counter >= 1200 {
+                        return Err(());
+                    } else {
+                        return Ok(());
+                    }
+                });
+            }));
+        }
 
-        error!("Turbine: Total time: {}", (end-start) as f32 / 1000000f32);
-        error!("Turbine: ops/s: {}", 10000000f32 / ((end-start) as f32 / 1000000f32 / 1000f32));
+        for i in 0..1200 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i as i32;
+            t.write(x);
+
+            // Give the EPs a chance to park between bursts
+            if i % 100 == 0 {
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        for f in futures {
+            if f.join().is_err() == true {panic!()}
+        }
     }
 
+    #[test]
+    fn test_start_with_timeout() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let (tx, rx): (Sender<(isize, u64)>, Receiver<(isize, u64)>) = channel();
+
+        let _future = thread::spawn(move|| {
+            // Shared by both closures
+            let counter = Cell::new(0);
+            let mut timeouts = 0;
+            let mut last_cursor = 0;
+            let _ = event_processor.start_with_timeout::<_, _, _, BlockingWait>(Duration::from_millis(20),
+                |data: &[TestSlot]| -> Result<(),()> {
+                    counter.set(counter.get() + data.len());
+                    Ok(())
+                },
+                |cursor: u64| -> Result<(),()> {
+                    timeouts += 1;
+                    last_cursor = cursor;
+
+                    // Stop once the ring has gone quiet after all the writes
+                    if counter.get() == 10 {
+                        return Err(());
+                    } else {
+                        return Ok(());
+                    }
+                });
+            let _ = tx.send((timeouts, last_cursor));
+        });
+
+        // Let the EP time out a few times on an empty ring
+        thread::sleep(Duration::from_millis(100));
+
+        for i in 0..10 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
 
+        let (timeouts, last_cursor) = rx.recv().unwrap();
+        assert!(timeouts > 1);
+        assert!(last_cursor == 10);
+    }
 
     #[test]
-    fn bench_turbine_latency() {
-        let path = Path::new("turbine_latency.csv");
-        let mut file = match File::create(&path) {
-            Err(why) => panic!("couldn't create file: {}", why),
-            Ok(file) => file,
-        };
+    fn test_batch_slices() {
+        let mut t: Turbine<TestSlot> = Turbine::new(8);
+        let e1 = t.ep_new().unwrap();
 
-        let mut t: Turbine<TestSlotU64> = Turbine::new(1048576);
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let future = thread::spawn(move|| {
+            let mut counter = 0;
+            let _ = event_processor.start::<_, _, BlockingWait>(|data: &[TestSlot]| -> Result<(),()> {
+                // Each batch must be exactly the new events, wherever in the ring it starts
+                for d in data.iter() {
+                    assert!(d.value == counter);
+                    counter += 1;
+                }
+                Ok(())
+            });
+            counter
+        });
+
+        // Small bursts, so batches start at different offsets and some wrap around
+        for i in 0..40 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+            if i % 3 == 0 {
+                thread::sleep(Duration::from_millis(2));
+            }
+        }
+        t.halt();
+
+        assert!(future.join().unwrap() == 40);
+    }
+
+    #[test]
+    fn test_halt_drains() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
         let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        let _ = t.ep_depends(e2, e1);
 
-        let event_processor = t.ep_finalize(e1);
+        let mut futures = Vec::with_capacity(2);
+        for token in vec![e1, e2] {
+            let event_processor = t.ep_finalize(token).unwrap();
+            futures.push(thread::spawn(move|| {
+                let mut counter = 0;
+                let cursor = event_processor.start::<_, _, BlockingWait>(|data: &[TestSlot]| -> Result<(),()> {
+                    // Slow consumer, so there is still data in flight when halted
+                    thread::sleep(Duration::from_millis(1));
+                    counter += data.len();
+                    Ok(())
+                });
+                (cursor.unwrap(), counter)
+            }));
+        }
+
+        for i in 0..500 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        let handle = t.halt_handle();
+        assert!(handle.is_halted() == false);
+        handle.halt();
+        assert!(t.halt_handle().is_halted() == true);
+
+        for f in futures {
+            let (cursor, counter) = f.join().unwrap();
+            assert!(cursor == 500);
+            assert!(counter == 500);
+        }
+        assert!(t.join() == vec![500, 500]);
+    }
+
+    #[test]
+    fn test_claim_publish() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
+
+        let _future = thread::spawn(move|| {
+            let mut counter = 0;
+            let mut last = -1;
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+                for x in data.iter() {
+                    assert!(last + 1 == x.value);
+                    counter += 1;
+                    last = x.value;
+                }
+
+                if counter >= 1200 {
+                    return Err(());
+                } else {
+                    return Ok(());
+                }
+            });
+            let _ = tx.send(1);
+        });
+
+        for i in 0..1200 {
+            if i % 2 == 0 {
+                let mut slot = t.claim();
+                assert!(slot.sequence() == i as u64);
+                slot.value = i;
+                slot.publish();
+            } else {
+                let mut slot = t.claim();
+                slot.value = i;
+            }
+        }
+        assert!(t.current_pos == 1200);
+        assert!(t.cursors.get(0).unwrap().load() == 1200);
+
+        if rx.recv().is_err() == true {panic!()}
+    }
+
+    #[test]
+    fn test_claim_batch_rollover() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
+
+        let _future = thread::spawn(move|| {
+            let mut counter = 0;
+            let mut last = -1;
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+                for x in data.iter() {
+                    assert!(last + 1 == x.value);
+                    counter += 1;
+                    last = x.value;
+                }
+
+                if counter >= 4000 {
+                    return Err(());
+                } else {
+                    return Ok(());
+                }
+            });
+            let _ = tx.send(1);
+        });
+
+        // 40 doesn't divide 1024, so batches will straddle the end of the ring
+        let mut value = 0;
+        for _ in 0..100 {
+            let mut batch = t.claim_batch(40);
+            assert!(batch.len() == 40);
+            assert!(batch.sequence() == value as u64);
+            for i in 0..batch.len() {
+                batch[i].value = value;
+                value += 1;
+            }
+        }
+        assert!(t.current_pos == 4000);
+
+        if rx.recv().is_err() == true {panic!()}
+    }
+
+    #[test]
+    fn test_multi_producer_claim_batch() {
+        let mut t: Turbine<TestSlot> = Turbine::new_multi_producer(16);
+        let e1 = t.ep_new().unwrap();
+        let _event_processor = t.ep_finalize(e1).unwrap();
+
+        let producer = t.producer().unwrap();
+        {
+            let mut batch = producer.claim_batch(100);
+            assert!(batch.len() == 16);
+            let (first, second) = batch.as_mut_slices();
+            assert!(first.len() == 16);
+            assert!(second.len() == 0);
+        }
+        assert!(t.cursors.get(0).unwrap().load() == 16);
+    }
+
+    #[test]
+    fn test_try_write_full() {
+        let mut t: Turbine<TestSlot> = Turbine::new(16);
+        let e1 = t.ep_new().unwrap();
+
+        let _event_processor = t.ep_finalize(e1).unwrap();
+        assert!(t.remaining_capacity() == 16);
+
+        for i in 0..16 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            assert!(t.try_write(x).is_ok());
+        }
+        assert!(t.remaining_capacity() == 0);
+
+        let mut x: TestSlot = Slot::new();
+        x.value = 99;
+        let x = match t.try_write(x) {
+            Ok(_) => panic!("Ring should be full"),
+            Err(d) => d
+        };
+        assert!(x.value == 99);
+        assert!(t.current_pos == 16);
+
+        // Pretend the EP consumed 4 events
+        t.cursors.get(1).unwrap().store(4);
+        assert!(t.remaining_capacity() == 4);
+        assert!(t.try_write(x).is_ok());
+    }
+
+    #[test]
+    fn test_multi_producer_try_write_full() {
+        let mut t: Turbine<TestSlot> = Turbine::new_multi_producer(16);
+        let e1 = t.ep_new().unwrap();
+        let _event_processor = t.ep_finalize(e1).unwrap();
+
+        let producer = t.producer().unwrap();
+        for _ in 0..16 {
+            assert!(producer.try_write(Slot::new()).is_ok());
+        }
+        assert!(producer.remaining_capacity() == 0);
+        assert!(producer.try_write(Slot::new()).is_err());
+
+        // A failed attempt must not claim a sequence
+        assert!(t.cursors.get(0).unwrap().load() == 16);
+    }
+
+    struct RecordingHandler {
+        started: bool,
+        shutdown: bool,
+        sequences: Vec<u64>,
+        batch_ends: Vec<u64>
+    }
+
+    impl EventHandler<TestSlot> for RecordingHandler {
+        type Error = ();
+
+        fn on_event(&mut self, event: &TestSlot, sequence: u64, end_of_batch: bool) -> Result<(), ()> {
+            assert!(self.started == true);
+            assert!(event.value as u64 == sequence);
+            self.sequences.push(sequence);
+            if end_of_batch {
+                self.batch_ends.push(sequence);
+            }
+
+            match sequence {
+                1499 => Err(()),
+                _ => Ok(())
+            }
+        }
+
+        fn on_start(&mut self) {
+            self.started = true;
+        }
+
+        fn on_shutdown(&mut self) {
+            self.shutdown = true;
+        }
+    }
+
+    #[test]
+    fn test_event_handler() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let future = thread::spawn(move|| {
+            let mut handler = RecordingHandler {
+                started: false,
+                shutdown: false,
+                sequences: vec![],
+                batch_ends: vec![]
+            };
+            let result = event_processor.start_with_handler::<_, BusyWait>(&mut handler);
+            assert!(result == Err(()));
+            handler
+        });
+
+        for i in 0..1500 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        let handler = future.join().unwrap();
+        assert!(handler.shutdown == true);
+        assert!(handler.sequences == (0..1500).collect::<Vec<u64>>());
+
+        // Every batch ends exactly once, and the final event always ends a batch
+        assert!(handler.batch_ends.len() > 0);
+        assert!(*handler.batch_ends.last().unwrap() == 1499);
+        for w in handler.batch_ends.windows(2) {
+            assert!(w[0] < w[1]);
+        }
+    }
+
+    #[test]
+    fn test_handler_error_mid_batch() {
+        struct FailAt(i32);
+
+        impl EventHandler<TestSlot> for FailAt {
+            type Error = i32;
+
+            fn on_event(&mut self, event: &TestSlot, _: u64, _: bool) -> Result<(), i32> {
+                match event.value == self.0 {
+                    true => Err(event.value),
+                    false => Ok(())
+                }
+            }
+        }
+
+        let mut t: Turbine<TestSlot> = Turbine::new(16);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        t.ep_depends(e2, e1).unwrap();
+        let ep1 = t.ep_finalize(e1).unwrap();
+        let mut p2 = t.ep_finalize(e2).unwrap().poller();
+
+        for i in 0..10 {
+            t.write(TestSlot { value: i });
+        }
+
+        // All ten events arrive in one batch, but the EP stops just past the failing one
+        assert!(ep1.start_with_handler::<_, BusyWait>(&mut FailAt(4)) == Err(4));
+
+        let mut c2 = Collector { seen: vec![], batches: 0 };
+        let _ = p2.poll(&mut c2);
+        assert!(c2.seen == (0..5).map(|i| (i as u64, i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_start_mut() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        t.ep_mutable(e1).unwrap();
+        t.ep_depends(e2, e1).unwrap();
+
+        let ep1 = t.ep_finalize(e1).unwrap();
+        let ep2 = t.ep_finalize(e2).unwrap();
+        assert!(t.ep_mutable(e1) == Err(TurbineError::AlreadyFinalized));
+
+        let _f1 = thread::spawn(move|| {
+            let _ = ep1.start_mut::<_, _, BusyWait>(|data: &mut [TestSlot]| -> Result<(),()> {
+                for x in data.iter_mut() {
+                    x.value *= 2;
+                }
+                Ok(())
+            });
+        });
+
+        let f2 = thread::spawn(move|| {
+            let mut counter = 0;
+            let _ = ep2.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+                for x in data.iter() {
+                    assert!(x.value == counter * 2);
+                    counter += 1;
+                }
+                match counter {
+                    1200 => Err(()),
+                    _ => Ok(())
+                }
+            });
+        });
+
+        for i in 0..1200 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+        f2.join().unwrap();
+    }
+
+    #[test]
+    fn test_mutable_unordered() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        t.ep_mutable(e2).unwrap();
+        assert!(t.ep_mutable(5) == Err(TurbineError::UnknownProcessor(5)));
+        let p1 = t.ep_new_pool(2).unwrap();
+        assert!(t.ep_mutable(p1) == Err(TurbineError::WorkerPoolToken(p1)));
+
+        match t.ep_finalize(e1) {
+            Err(TurbineError::InvalidDependency(GraphError::UnorderedMutation { processor: 1, other: 0 })) => {},
+            _ => panic!("Unordered mutable EP should fail to finalize")
+        }
+    }
+
+    #[test]
+    fn test_worker_pool() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let p1 = t.ep_new_pool(3).unwrap();
+        let e1 = t.ep_new().unwrap();
+        t.ep_depends(e1, p1).unwrap();
+
+        assert!(t.ep_finalize(p1).is_err());
+        assert!(t.pool_finalize(e1).is_err());
+
+        let pool = t.pool_finalize(p1).unwrap();
+        assert!(t.pool_finalize(p1).err() == Some(TurbineError::AlreadyTaken(p1)));
+        assert!(pool.size() == 3);
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let (tx, rx): (Sender<u64>, Receiver<u64>) = channel();
+
+        let mut workers = vec![];
+        for worker in pool.workers() {
+            let tx = tx.clone();
+            workers.push(thread::spawn(move|| {
+                let result = worker.start::<_, _, BlockingWait>(|x: &TestSlot, sequence: u64| -> Result<(),()> {
+                    assert!(x.value as u64 == sequence);
+                    let _ = tx.send(sequence);
+                    Ok(())
+                });
+                assert!(result.is_ok());
+            }));
+        }
+        drop(tx);
+
+        let downstream = thread::spawn(move|| {
+            let mut counter = 0;
+            let _ = event_processor.start::<_, _, BlockingWait>(|data: &[TestSlot]| -> Result<(),()> {
+                for x in data.iter() {
+                    assert!(x.value == counter);
+                    counter += 1;
+                }
+                match counter {
+                    1200 => Err(()),
+                    _ => Ok(())
+                }
+            });
+        });
+
+        for i in 0..1200 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+        downstream.join().unwrap();
+
+        t.halt();
+        for w in workers {
+            w.join().unwrap();
+        }
+
+        // Each event was handled by exactly one worker
+        let mut sequences: Vec<u64> = rx.iter().collect();
+        sequences.sort();
+        assert!(sequences == (0..1200).collect::<Vec<u64>>());
+    }
+
+    /// Records how far it has got, and checks that its upstream stages are further along
+    struct StageHandler {
+        progress: Arc<AtomicUsize>,
+        upstream: Vec<Arc<AtomicUsize>>
+    }
+
+    impl EventHandler<TestSlot> for StageHandler {
+        type Error = ();
+
+        fn on_event(&mut self, event: &TestSlot, sequence: u64, _: bool) -> Result<(), ()> {
+            assert!(event.value as u64 == sequence);
+            for u in self.upstream.iter() {
+                assert!(u.load(Ordering::SeqCst) > sequence as usize);
+            }
+            self.progress.store(sequence as usize + 1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline_diamond() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let a = Arc::new(AtomicUsize::new(0));
+        let b = Arc::new(AtomicUsize::new(0));
+        let c = Arc::new(AtomicUsize::new(0));
+
+        let pipeline = t.handle_with_all(vec![
+                StageHandler { progress: a.clone(), upstream: vec![] },
+                StageHandler { progress: b.clone(), upstream: vec![] }
+            ])
+            .then(StageHandler { progress: c.clone(), upstream: vec![a.clone(), b.clone()] })
+            .start::<BlockingWait>().unwrap();
+        assert!(t.ep_new() == Err(TurbineError::AlreadyFinalized));
+
+        for i in 0..1200 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+        assert!(pipeline.thread_names() == vec!["turbine-ep-0", "turbine-ep-1", "turbine-ep-2"]);
+        t.halt();
+
+        let results: Vec<Result<u64, ()>> = pipeline.join().into_iter()
+                                                    .map(|r| r.map(|(_, cursor)| cursor))
+                                                    .collect();
+        assert!(results == vec![Ok(1200), Ok(1200), Ok(1200)]);
+        assert!(c.load(Ordering::SeqCst) == 1200);
+    }
+
+    #[test]
+    fn test_turbine_start() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        let p1 = t.ep_new_pool(2).unwrap();
+        t.ep_depends(e2, e1).unwrap();
+        t.ep_depends(p1, e2).unwrap();
+
+        let a = Arc::new(AtomicUsize::new(0));
+        let b = Arc::new(AtomicUsize::new(0));
+        let stage = |progress: &Arc<AtomicUsize>, upstream: Vec<Arc<AtomicUsize>>| {
+            Box::new(StageHandler { progress: progress.clone(), upstream: upstream }) as BoxedHandler<TestSlot, ()>
+        };
+
+        // Tokens are checked before anything is finalized
+        assert!(t.start::<_, BlockingWait>(vec![(e1, stage(&a, vec![])), (e1, stage(&a, vec![]))])
+                == Err(TurbineError::AlreadyTaken(e1)));
+        assert!(t.start::<_, BlockingWait>(vec![(e1, stage(&a, vec![])), (p1, stage(&b, vec![]))])
+                == Err(TurbineError::WorkerPoolToken(p1)));
+
+        let pipeline = t.start::<_, BlockingWait>(vec![(e1, stage(&a, vec![])), (e2, stage(&b, vec![a.clone()]))]).unwrap();
+        assert!(pipeline.thread_names() == vec!["turbine-ep-0", "turbine-ep-1"]);
+        assert!(t.ep_finalize(e2).err() == Some(TurbineError::AlreadyTaken(e2)));
+        for worker in t.pool_finalize(p1).unwrap().workers() {
+            thread::spawn(move|| {
+                worker.start::<_, (), BusyWait>(|_: &TestSlot, _: u64| Ok(()))
+            });
+        }
+
+        for i in 0..300 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        let results: Vec<Result<u64, ()>> = pipeline.shutdown().into_iter()
+                                                    .map(|r| r.map(|(_, cursor)| cursor))
+                                                    .collect();
+        assert!(results == vec![Ok(300), Ok(300)]);
+        assert!(t.join() == vec![300, 300, 300]);
+        assert!(b.load(Ordering::SeqCst) == 300);
+    }
+
+    #[test]
+    fn test_spawn_returns_handler() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let thread = event_processor.spawn::<_, BlockingWait>(RecordingHandler {
+            started: false,
+            shutdown: false,
+            sequences: vec![],
+            batch_ends: vec![]
+        }).unwrap();
+        assert!(thread.name() == "turbine-ep-0");
+
+        for i in 0..500 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+        t.halt();
+
+        let (handler, cursor) = thread.join().ok().unwrap();
+        assert!(cursor == 500);
+        assert!(handler.shutdown == true);
+        assert!(handler.sequences == (0..500).collect::<Vec<u64>>());
+    }
+
+    /// Panics on every event whose value is a multiple of 100
+    struct FragileHandler {
+        handled: usize
+    }
+
+    impl EventHandler<TestSlot> for FragileHandler {
+        type Error = ();
+
+        fn on_event(&mut self, event: &TestSlot, _: u64, _: bool) -> Result<(), ()> {
+            if event.value % 100 == 0 {
+                panic!("bad event {}", event.value);
+            }
+            self.handled += 1;
+            Ok(())
+        }
+    }
+
+    struct FailedSequences {
+        failed: Vec<u64>
+    }
+
+    impl ExceptionHandler<TestSlot> for FailedSequences {
+        fn on_exception(&mut self, _: Box<Any + Send>, event: &TestSlot, sequence: u64) -> ExceptionAction {
+            assert!(event.value as u64 == sequence);
+            self.failed.push(sequence);
+            ExceptionAction::Continue
+        }
+    }
+
+    #[test]
+    fn test_exception_handler_continues() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let future = thread::spawn(move|| {
+            let mut handler = FragileHandler { handled: 0 };
+            let mut exceptions = FailedSequences { failed: vec![] };
+            let result = event_processor.start_with_exception_handler::<_, _, BusyWait>(&mut handler, &mut exceptions);
+            assert!(result == Ok(1500));
+            (handler, exceptions)
+        });
+
+        // More than a full ring, so a wedged EP would block the writer
+        for i in 0..1500 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+        t.halt();
+
+        let (handler, exceptions) = future.join().unwrap();
+        assert!(handler.handled == 1485);
+        assert!(exceptions.failed == (0..15).map(|i| i * 100).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_start_catches_panics() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let event_processor = t.ep_finalize(e1).unwrap();
+
+        for i in 1..150 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        // The panic halts the Turbine, and the EP drains the rest instead of dying
+        let future = thread::spawn(move|| {
+            event_processor.start::<_, _, BlockingWait>(|data: &[TestSlot]| -> Result<(), ()> {
+                if data.iter().any(|x| x.value == 100) {
+                    panic!("bad batch");
+                }
+                Ok(())
+            })
+        });
+        assert!(future.join().unwrap() == Ok(149));
+        assert!(t.halt_handle().is_halted() == true);
+    }
+
+    #[test]
+    fn test_exception_handler_halts() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let pipeline = t.handle_with(FragileHandler { handled: 0 })
+                        .with_exception_handler(HaltOnException)
+                        .start::<BlockingWait>().unwrap();
+
+        for i in 1..150 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        // The panic at 100 halts the Turbine, but the EP still drains the rest
+        let results = pipeline.join();
+        assert!(t.halt_handle().is_halted() == true);
+        assert!(results.len() == 1);
+        assert!(results[0].is_ok());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        t.ep_name(e1, "journal").unwrap();
+        t.ep_depends(e2, e1).unwrap();
+        assert!(t.stats_handle().err() == Some(TurbineError::NotFinalized));
+
+        let ep1 = t.ep_finalize(e1).unwrap();
+        let _ep2 = t.ep_finalize(e2).unwrap();
+        assert!(t.ep_name(e1, "late") == Err(TurbineError::AlreadyFinalized));
+        let stats = t.stats_handle().unwrap();
+
+        for i in 0..60 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        // Only e1 runs, and stops after the first 60 events
+        let result = ep1.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+            match data.iter().any(|x| x.value == 59) {
+                true => Err(()),
+                false => Ok(())
+            }
+        });
+        assert!(result == Err(()));
+
+        for i in 60..100 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        let snapshot = thread::spawn(move|| stats.snapshot()).join().unwrap();
+        assert!(snapshot.producer_cursor == 100);
+        assert!(snapshot.capacity == 1024);
+        assert!(snapshot.occupancy == 100);
+
+        let ref journal = snapshot.processors[0];
+        assert!(journal.name == "journal");
+        assert!(journal.stopped == true);
+        assert!(journal.cursor == 60 && journal.lag == 40);
+
+        let ref second = snapshot.processors[1];
+        assert!(second.name == "turbine-ep-1");
+        assert!(second.cursor == 0 && second.lag == 100 && second.stopped == false);
+    }
+
+    #[test]
+    fn test_backpressure_stats() {
+        let mut t: Turbine<TestSlot> = Turbine::new(4);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+
+        let stalls = Arc::new(AtomicUsize::new(0));
+        let s = stalls.clone();
+        t.on_stall(Duration::from_millis(5), move|stall: &Stall| {
+            assert!(stall.bottleneck == Some(Bottleneck::Processor(1)));
+            assert!(stall.duration >= Duration::from_millis(5));
+            s.fetch_add(1, Ordering::SeqCst);
+        }).unwrap();
+
+        let ep1 = t.ep_finalize(e1).unwrap();
+        let ep2 = t.ep_finalize(e2).unwrap();
+        assert!(t.on_stall(Duration::from_millis(5), |_: &Stall| {}) == Err(TurbineError::AlreadyFinalized));
+        let stats = t.stats_handle().unwrap();
+
+        let _f1 = thread::spawn(move|| {
+            let _ = ep1.start::<_, _, BlockingWait>(|_: &[TestSlot]| -> Result<(),()> { Ok(()) });
+        });
+
+        // Fill the ring, without a stall
+        for _ in 0..4 {
+            t.write(Slot::new());
+        }
+        assert!(stats.snapshot().producer.waited_writes == 0);
+        while stats.snapshot().processors[0].cursor < 4 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // e2 holds the writer back until it starts
+        let _f2 = thread::spawn(move|| {
+            thread::sleep(Duration::from_millis(50));
+            let _ = ep2.start::<_, _, BlockingWait>(|_: &[TestSlot]| -> Result<(),()> { Ok(()) });
+        });
+        t.write(Slot::new());
+        t.halt();
+
+        let producer = stats.snapshot().producer;
+        assert!(producer.waited_writes == 1);
+        assert!(producer.spin_iterations > 1);
+        assert!(producer.wait_ns >= 5000000);
+        assert!(producer.bottleneck == Some(Bottleneck::Processor(1)));
+        assert!(stalls.load(Ordering::SeqCst) == 1);
+    }
+
+    #[test]
+    fn test_latency_tracking() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        t.track_latency().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        assert!(t.track_latency() == Err(TurbineError::AlreadyFinalized));
+        let stats = t.stats_handle().unwrap();
+
+        let future = thread::spawn(move|| {
+            event_processor.start::<_, _, BusyWait>(|_: &[TestSlot]| -> Result<(),()> { Ok(()) })
+        });
+
+        for i in 0..2000 {
+            if i % 2 == 0 {
+                t.write(Slot::new());
+            } else {
+                let mut batch = t.claim_batch(1);
+                batch[0].value = i;
+            }
+        }
+        t.halt();
+        assert!(future.join().unwrap() == Ok(2000));
+
+        let snapshot = stats.snapshot();
+        let latency = snapshot.processors[0].latency.as_ref().unwrap();
+        assert!(latency.count() == 2000);
+        assert!(latency.max() > 0);
+        assert!(latency.percentile(50.0) <= latency.percentile(99.0));
+        assert!(latency.percentile(99.0) <= latency.max());
+
+        // Off by default
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let _event_processor = t.ep_finalize(e1).unwrap();
+        assert!(t.stats_handle().unwrap().snapshot().processors[0].latency == None);
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let future = thread::spawn(move|| {
+            let mut counter = 0;
+            for item in event_processor.wait() {
+                let (sequence, event) = item.unwrap();
+                assert!(sequence == counter);
+                assert!(event.value as u64 == sequence);
+                counter += 1;
+            }
+            counter
+        });
+
+        // More than a full ring, so both sides have to be woken up
+        for i in 0..1500 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+            if i % 500 == 0 {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        t.halt();
+
+        // The stream ends once the halted EP has drained
+        assert!(future.join().unwrap() == 1500);
+    }
+
+    #[test]
+    fn test_sink() {
+        let mut t: Turbine<TestSlot> = Turbine::new(16);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let future = thread::spawn(move|| {
+            let mut counter = 0;
+            let _ = event_processor.start::<_, _, BlockingWait>(|data: &[TestSlot]| -> Result<(),()> {
+                for d in data.iter() {
+                    assert!(d.value == counter);
+                    counter += 1;
+                }
+                // Slow consumer, so the sink finds the ring full
+                thread::sleep(Duration::from_millis(1));
+                Ok(())
+            });
+            counter
+        });
+
+        let items = stream::iter_ok::<_, TurbineError>((0..500).map(|i| TestSlot { value: i }));
+        let (mut t, _) = t.send_all(items).wait().unwrap();
+        let snapshot = t.stats_handle().unwrap().snapshot();
+        assert!(snapshot.producer_cursor == 500);
+
+        // Sends which were parked on a full ring count as waiting writes
+        assert!(snapshot.producer.waited_writes > 0);
+        assert!(snapshot.producer.bottleneck == Some(Bottleneck::Processor(e1)));
+
+        t.halt();
+        assert!(future.join().unwrap() == 500);
+
+        // Nothing is read after a halt
+        match t.start_send(Slot::new()) {
+            Err(TurbineError::Halted) => {},
+            _ => panic!("Expected Halted")
+        }
+    }
+
+    struct Collector {
+        seen: Vec<(u64, i32)>,
+        batches: usize
+    }
+
+    impl EventHandler<TestSlot> for Collector {
+        type Error = ();
+
+        fn on_event(&mut self, event: &TestSlot, sequence: u64, end_of_batch: bool) -> Result<(), ()> {
+            self.seen.push((sequence, event.value));
+            if end_of_batch {
+                self.batches += 1;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_event_poller() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1024);
+        let e1 = t.ep_new().unwrap();
+        let e2 = t.ep_new().unwrap();
+        let _ = t.ep_depends(e2, e1);
+
+        let ep1 = t.ep_finalize(e1).unwrap();
+        let ep2 = t.ep_finalize(e2).unwrap();
+        let mut p1 = ep1.poller();
+        let mut p2 = ep2.poller();
+        let mut c1 = Collector { seen: vec![], batches: 0 };
+        let mut c2 = Collector { seen: vec![], batches: 0 };
+
+        assert!(p1.poll(&mut c1) == Ok(PollState::Idle));
+        assert!(p2.poll(&mut c2) == Ok(PollState::Idle));
+
+        for i in 0..3 {
+            let mut x: TestSlot = Slot::new();
+            x.value = i;
+            t.write(x);
+        }
+
+        // e2 is held back until e1 has seen the events
+        assert!(p2.poll(&mut c2) == Ok(PollState::Gating));
+        assert!(p1.poll(&mut c1) == Ok(PollState::Processing));
+        assert!(c1.seen == vec![(0, 0), (1, 1), (2, 2)]);
+        assert!(c1.batches == 1);
+        assert!(p1.poll(&mut c1) == Ok(PollState::Idle));
+
+        assert!(p2.poll(&mut c2) == Ok(PollState::Processing));
+        assert!(c2.seen == c1.seen);
+
+        // Once drained after a halt, the pollers are marked as stopped
+        t.halt();
+        assert!(p1.poll(&mut c1) == Ok(PollState::Idle));
+        assert!(p2.poll(&mut c2) == Ok(PollState::Idle));
+        let stats = t.stats_handle().unwrap().snapshot();
+        assert!(stats.processors[0].stopped && stats.processors[1].stopped);
+    }
+
+    #[test]
+    fn test_subscriber() {
+        let mut t: Turbine<TestSlot> = Turbine::new(16);
+        let e1 = t.ep_new().unwrap();
+        assert!(t.subscribe().is_err());
+
+        let mut poller = t.ep_finalize(e1).unwrap().poller();
+        let mut c1 = Collector { seen: vec![], batches: 0 };
+        for i in 0..3 {
+            t.write(TestSlot { value: i });
+        }
+        let _ = poller.poll(&mut c1);
+
+        // Starts at the producer's position
+        let mut subscriber = t.subscribe().unwrap();
+        assert!(subscriber.sequence() == 3);
+
+        let mut audit = Collector { seen: vec![], batches: 0 };
+        assert!(subscriber.poll(&mut audit) == Ok(PollState::Idle));
+        t.write(TestSlot { value: 3 });
+        t.write(TestSlot { value: 4 });
+        assert!(subscriber.poll(&mut audit) == Ok(PollState::Processing));
+        assert!(audit.seen == vec![(3, 3), (4, 4)]);
+
+        // The EP keeps up, but the subscriber now holds the producer back
+        for i in 5..21 {
+            t.write(TestSlot { value: i });
+            let _ = poller.poll(&mut c1);
+        }
+        assert!(t.remaining_capacity() == 0);
+        assert!(t.try_write(TestSlot { value: 21 }).is_err());
+
+        // The stats count the slots the subscriber holds
+        let stats = t.stats_handle().unwrap();
+        let snapshot = stats.snapshot();
+        assert!(snapshot.occupancy == 16);
+        assert!(snapshot.processors[0].lag == 0);
+        assert!(snapshot.subscribers == vec![SubscriberStats { id: subscriber.id(), cursor: 5, lag: 16 }]);
+
+        // A write which has to wait for it names the subscriber as the bottleneck
+        let release = thread::spawn(move|| {
+            thread::sleep(Duration::from_millis(20));
+            let _ = subscriber.poll(&mut audit);
+            subscriber
+        });
+        t.write(TestSlot { value: 21 });
+        let _ = poller.poll(&mut c1);
+        assert!(stats.snapshot().producer.bottleneck == Some(Bottleneck::Subscriber));
+
+        release.join().unwrap().detach();
+        assert!(stats.snapshot().subscribers.is_empty());
+        assert!(t.remaining_capacity() == 16);
+        assert!(t.try_write(TestSlot { value: 22 }).is_ok());
+    }
+
+    #[test]
+    fn test_subscriber_mutable() {
+        let mut t: Turbine<TestSlot> = Turbine::new(16);
+        let e1 = t.ep_new().unwrap();
+        t.ep_mutable(e1).unwrap();
+        let ep1 = t.ep_finalize(e1).unwrap();
+
+        let mut subscriber = t.subscribe().unwrap();
+        for i in 0..10 {
+            t.write(TestSlot { value: i });
+        }
+
+        // Published, but the mutable EP may still be modifying them
+        let mut audit = Collector { seen: vec![], batches: 0 };
+        assert!(subscriber.poll(&mut audit) == Ok(PollState::Gating));
+        assert!(audit.seen.is_empty());
+
+        let mut counter = 0;
+        let result = ep1.start_mut::<_, _, BusyWait>(|data: &mut [TestSlot]| -> Result<(),()> {
+            for x in data.iter_mut() {
+                x.value *= 2;
+            }
+            counter += data.len();
+            match counter {
+                10 => Err(()),
+                _ => Ok(())
+            }
+        });
+        assert!(result == Err(()));
+
+        // Only the modified events are seen, whether live or replayed
+        let doubled = (0..10).map(|i| (i as u64, i * 2)).collect::<Vec<_>>();
+        assert!(subscriber.poll(&mut audit) == Ok(PollState::Processing));
+        assert!(audit.seen == doubled);
+
+        let mut replay = t.subscribe_from(StartAt::Oldest).unwrap();
+        let mut history = Collector { seen: vec![], batches: 0 };
+        assert!(replay.poll(&mut history) == Ok(PollState::Processing));
+        assert!(history.seen == doubled);
+    }
+
+    #[test]
+    fn test_subscribe_from() {
+        let mut t: Turbine<TestSlot> = Turbine::new(16);
+        let e1 = t.ep_new().unwrap();
+        let mut poller = t.ep_finalize(e1).unwrap().poller();
+        let mut c1 = Collector { seen: vec![], batches: 0 };
+
+        for i in 0..10 {
+            t.write(TestSlot { value: i });
+        }
+
+        // Nothing has been handled yet, so the whole backlog is still safe to read
+        let mut monitor = t.subscribe_from(StartAt::Oldest).unwrap();
+        assert!(monitor.sequence() == 0);
+        assert!(t.subscribe_from(StartAt::Sequence(4)).unwrap().sequence() == 4);
+        assert!(t.subscribe_from(StartAt::Latest).unwrap().sequence() == 10);
+        assert!(t.subscribe_from(StartAt::Sequence(11)).err() == Some(TurbineError::SequenceUnavailable(11)));
+
+        // The ring hasn't wrapped, so handled events can still be replayed
+        let _ = poller.poll(&mut c1);
+        drop(monitor);
+        assert!(t.subscribe_from(StartAt::Oldest).unwrap().sequence() == 0);
+        assert!(t.subscribe_from(StartAt::Sequence(4)).unwrap().sequence() == 4);
+
+        // A replaying subscriber sees the backlog and gates the producer from there
+        for i in 10..14 {
+            t.write(TestSlot { value: i });
+        }
+        monitor = t.subscribe_from(StartAt::Sequence(12)).unwrap();
+        let _ = poller.poll(&mut c1);
+        for i in 14..28 {
+            t.write(TestSlot { value: i });
+            let _ = poller.poll(&mut c1);
+        }
+        assert!(t.try_write(TestSlot { value: 28 }).is_err());
+
+        let mut audit = Collector { seen: vec![], batches: 0 };
+        assert!(monitor.poll(&mut audit) == Ok(PollState::Processing));
+        assert!(audit.seen == (12..28).map(|i| (i as u64, i)).collect::<Vec<_>>());
+        assert!(t.try_write(TestSlot { value: 28 }).is_ok());
+
+        // Oldest goes back a whole ring, however far the other consumers are, and the
+        // producer stops at once rather than writing on with its cached position
+        let mut replay = t.subscribe_from(StartAt::Oldest).unwrap();
+        assert!(replay.sequence() == 13);
+        assert!(t.subscribe_from(StartAt::Sequence(12)).err() == Some(TurbineError::SequenceUnavailable(12)));
+        assert!(t.try_write(TestSlot { value: 29 }).is_err());
+
+        let mut history = Collector { seen: vec![], batches: 0 };
+        assert!(replay.poll(&mut history) == Ok(PollState::Processing));
+        assert!(history.seen == (13..29).map(|i| (i as u64, i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn bench_chan_10m() {
+
+        let (tx_bench, rx_bench): (Sender<isize>, Receiver<isize>) = channel();
+
+
+        let _future = thread::spawn(move|| {
+            for _ in 0..10000000 {
+                let _ = tx_bench.send(1);
+            }
+        });
+
+        let start = precise_time_ns();
+        let mut counter = 0;
+        for _ in 0..10000000 {
+            counter += rx_bench.recv().ok().unwrap();
+        }
+        let end = precise_time_ns();
+        assert!(counter > 0);
+
+        let _ = _future.join();
+
+        error!("Channel: Total time: {}", (end-start) as f32 / 1000000f32);
+        error!("Channel: ops/s: {}", 10000000f32 / ((end-start) as f32 / 1000000f32 / 1000f32));
+    }
+
+    #[test]
+    fn bench_turbine_10m() {
+        let mut t: Turbine<TestSlot> = Turbine::new(1048576);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
+        let (tx, rx): (Sender<isize>, Receiver<isize>) = channel();
+
+        let _future = thread::spawn(move|| {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlot]| -> Result<(),()> {
+                let mut counter = 0;
+                for _ in data.iter() {
+                    counter += data[0].value;
+                }
+
+                if counter == 10000000 {
+                    return Err(());
+                } else {
+                    return Ok(());
+                }
+
+            });
+            let _ = tx.send(1);
+        });
+
+        let start = precise_time_ns();
+        for _ in 0..10000000 {
+            let mut s: TestSlot = Slot::new();
+            s.value = 1;
+            t.write(s);
+        }
+
+        let _ = rx.recv();
+        let end = precise_time_ns();
+
+
+        error!("Turbine: Total time: {}", (end-start) as f32 / 1000000f32);
+        error!("Turbine: ops/s: {}", 10000000f32 / ((end-start) as f32 / 1000000f32 / 1000f32));
+    }
+
+
+
+    #[test]
+    fn bench_turbine_latency() {
+        let path = Path::new("turbine_latency.csv");
+        let mut file = match File::create(&path) {
+            Err(why) => panic!("couldn't create file: {}", why),
+            Ok(file) => file,
+        };
+
+        let mut t: Turbine<TestSlotU64> = Turbine::new(1048576);
+        let e1 = t.ep_new().unwrap();
+
+        let event_processor = t.ep_finalize(e1).unwrap();
         let (tx, rx): (Sender<Vec<u64>>, Receiver<Vec<u64>>) = channel();
         let mut latencies = Vec::with_capacity(1000000);
 
         let _future = thread::spawn(move|| {
-            event_processor.start::<_, BusyWait>(|data: &[TestSlotU64]| -> Result<(),()> {
+            let _ = event_processor.start::<_, _, BusyWait>(|data: &[TestSlotU64]| -> Result<(),()> {
                 let mut counter: isize = 0;
                 for d in data.iter() {
                     let end = precise_time_ns();
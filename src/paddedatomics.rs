@@ -34,6 +34,12 @@ impl Padded64 {
         self.counter.store(x, SeqCst);
     }
 
+    /// Store `new` if the current value is `old`, returning the previous value
+    #[inline]
+    pub fn compare_and_swap(&self, old: u64, new: u64) -> u64 {
+        self.counter.compare_and_swap(old, new, SeqCst)
+    }
+
     #[inline]
     pub fn reset(&self) {
         self.store(0);
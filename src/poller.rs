@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use waitstrategy::WaitSignal;
+use paddedatomics::Padded64;
+use ringbuffer::{RingBuffer, Slot};
+use sequencer::{self, AvailableBuffer};
+use handler::EventHandler;
+use latency::Latency;
+
+/// The outcome of a single `EventPoller::poll()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PollState {
+    /// At least one event was handled
+    Processing,
+
+    /// Events have been published, but the poller's dependencies have not finished
+    /// with them yet
+    Gating,
+
+    /// Nothing new has been published
+    Idle
+}
+
+/// A pull-based alternative to the `EventProcessor::start()` loops
+///
+/// `start()` takes over its thread until the EP stops.  An EventPoller instead handles
+/// whatever is available each time `poll()` is called and returns straight away, so a
+/// single thread can interleave consuming Turbine with its own event loop.  The poller
+/// is still a node of the graph: it gates the producer, and other EPs may depend on it,
+/// so it must be polled regularly.
+///
+/// Once the Turbine has been halted and the poller has drained, it is marked as stopped
+/// (so that EPs depending on it can finish draining too) and keeps returning `Idle`.
+///
+/// Obtained from `EventProcessor::poller()`.
+///
+///##Example
+///
+///```
+///let mut poller = event_processor.poller();
+///loop {
+///    match poller.poll(&mut journaller).unwrap() {
+///        PollState::Processing => {},
+///        PollState::Gating | PollState::Idle => service_sockets()
+///    }
+///}
+///```
+pub struct EventPoller<T> {
+    graph: Arc<Vec<Vec<usize>>>,
+    cursors: Arc<Vec<Padded64>>,
+    stopped: Arc<Vec<AtomicBool>>,
+    token: usize,
+    ring: Arc<RingBuffer<T>>,
+    available: Option<Arc<AvailableBuffer>>,
+    signal: Arc<WaitSignal>,
+    latency: Option<Arc<Latency>>
+}
+
+impl<T: Slot> EventPoller<T> {
+
+    /// Instantiate a new EventPoller.
+    ///
+    /// This is for internal use only, see `EventProcessor::new()` for the parameters.
+    pub fn new(ring: Arc<RingBuffer<T>>, graph: Arc<Vec<Vec<usize>>>, cursors: Arc<Vec<Padded64>>,
+               stopped: Arc<Vec<AtomicBool>>, available: Option<Arc<AvailableBuffer>>,
+               signal: Arc<WaitSignal>, token: usize,
+               latency: Option<Arc<Latency>>) -> EventPoller<T> {
+        EventPoller::<T> {
+            graph: graph,
+            cursors: cursors,
+            stopped: stopped,
+            token: token,
+            ring: ring,
+            available: available,
+            signal: signal,
+            latency: latency
+        }
+    }
+
+    /// The token of this poller, as returned by `Turbine::ep_new()`
+    pub fn token(&self) -> usize {
+        self.token
+    }
+
+    /// Handle every event which is currently available, without waiting
+    ///
+    /// Each event is passed to `handler`, with `end_of_batch` set on the last one.  If the
+    /// handler returns an Err, the cursor moves past the failing event (so it is not seen
+    /// again), the rest of the batch is left for the next `poll()` and the Err is returned.
+    pub fn poll<H>(&mut self, handler: &mut H) -> Result<PollState, H::Error>
+    where H: EventHandler<T> + ?Sized {
+        let ref cursor = (*self.cursors).as_slice()[self.token + 1];
+        let sequence = cursor.load();
+
+        // See EventProcessor::run(), this must be checked before looking for events
+        let drained = self.signal.is_alerted() && self.dependencies_stopped();
+
+        let available = self.available_from(sequence);
+        if available == sequence {
+            if drained && !self.stopped.as_slice()[self.token + 1].load(Ordering::SeqCst) {
+                self.stopped.as_slice()[self.token + 1].store(true, Ordering::SeqCst);
+                self.signal.notify();
+            }
+
+            return match self.published(sequence) {
+                true => Ok(PollState::Gating),
+                false => Ok(PollState::Idle)
+            };
+        }
+
+        let latency = self.latency.as_ref().map(|l| (&**l, self.token));
+        try!(sequencer::handle_events(&self.ring, handler, sequence, available, cursor, &self.signal, latency));
+        Ok(PollState::Processing)
+    }
+
+    /// The end of the run of events starting at `sequence` which every dependency has
+    /// finished with
+    fn available_from(&self, sequence: u64) -> u64 {
+        sequencer::available_from(&self.graph.as_slice()[self.token], self.cursors.as_slice(), &self.available,
+                                  sequence, 18446744073709551615)
+    }
+
+    /// Returns true if the producer has published `sequence`
+    fn published(&self, sequence: u64) -> bool {
+        match self.available {
+            Some(ref published) => published.is_available(sequence),
+            None => self.cursors.as_slice()[0].load() > sequence
+        }
+    }
+
+    /// Returns true if every dependency of this poller has stopped, see `sequencer::dependencies_stopped()`
+    fn dependencies_stopped(&self) -> bool {
+        sequencer::dependencies_stopped(&self.graph.as_slice()[self.token], self.stopped.as_slice())
+    }
+}
@@ -75,6 +75,28 @@ impl<T: Slot> RingBuffer<T> {
         &self.entries.get().as_ref().unwrap()[from .. from + size]
     }
 
+    // Unsafe because we have no guarantees nobody else is reading or writing this slot
+    pub unsafe fn get_mut(&self, position: usize) -> &mut T {
+        &mut self.entries.get().as_mut().unwrap()[position]
+    }
+
+    // Unsafe because we have no guarantees nobody else is reading or writing these slots
+    pub unsafe fn get_range_mut(&self, from: usize, size: usize) -> &mut [T] {
+        &mut self.entries.get().as_mut().unwrap()[from .. from + size]
+    }
+
+    /// Mutable views over `size` slots starting at `from`, split where the ring wraps
+    ///
+    /// Unsafe for the same reasons as `get_range_mut()`
+    pub unsafe fn get_wrapping_mut(&self, from: usize, size: usize) -> (&mut [T], &mut [T]) {
+        let capacity = self.get_capacity();
+        if from + size <= capacity {
+            (self.get_range_mut(from, size), self.get_range_mut(0, 0))
+        } else {
+            (self.get_range_mut(from, capacity - from), self.get_range_mut(0, from + size - capacity))
+        }
+    }
+
     // Unsafe because we have no guarantees the caller won't invalidate this slot
     pub unsafe fn write(&self, position: usize, data: T) {
         self.entries.get().as_mut().unwrap()[position] = data;
@@ -112,4 +134,18 @@ mod tests {
     fn new_ringbuff_zero() {
         let _: RingBuffer<TestSlot> = RingBuffer::new(0);
     }
+
+    #[test]
+    fn wrapping_mut() {
+        let r: RingBuffer<TestSlot> = RingBuffer::new(8);
+        unsafe {
+            let (first, second) = r.get_wrapping_mut(2, 4);
+            assert!(first.len() == 4);
+            assert!(second.len() == 0);
+
+            let (first, second) = r.get_wrapping_mut(6, 5);
+            assert!(first.len() == 2);
+            assert!(second.len() == 3);
+        }
+    }
 }
@@ -0,0 +1,486 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::Ordering::SeqCst;
+use std::cmp::min;
+use atomicnum::AtomicNum;
+use paddedatomics::Padded64;
+use ringbuffer::{RingBuffer, Slot};
+use waitstrategy::WaitSignal;
+use handler::EventHandler;
+use claim::{Claim, BatchClaim, Publisher};
+use error::TurbineError;
+use backpressure::{Backpressure, Waiting};
+use latency::Latency;
+use subscriber::DynamicCursors;
+use futures::{Sink, StartSend, AsyncSink, Poll, Async};
+
+/// Find the minimum EventProcessor cursor, or `default` if there are no EPs
+///
+/// `cursors` is the full cursor vector; the root cursor at index 0 is skipped.
+pub fn minimum_cursor(cursors: &[Padded64], default: u64) -> u64 {
+    let mut min_cursor = default;
+    for v in cursors.iter().skip(1) {
+        min_cursor = min(min_cursor, v.load());
+    }
+    min_cursor
+}
+
+/// The end (exclusive) of the events from `sequence` onwards which every one of `deps`
+/// has finished with, and which have been published, looking no further than `limit`
+///
+/// `deps` are indices into `cursors`, as stored in the dependency graph.  `published`
+/// holds the publication markers in multi-producer mode.  Shared by all the consumers.
+pub fn available_from(deps: &[usize], cursors: &[Padded64], published: &Option<Arc<AvailableBuffer>>,
+                      sequence: u64, limit: u64) -> u64 {
+    let mut available = limit;
+    for &dep in deps.iter() {
+        available = min(available, cursors[dep].load());
+    }
+    match *published {
+        Some(ref published) => published.highest_published(sequence, available),
+        None => available
+    }
+}
+
+/// Pass the events `[from, to)` to `handler`, then move `cursor` past them
+///
+/// Returns the new cursor position.  If the handler fails, the cursor only moves past
+/// the failing event, and the error is returned.  `latency` holds the latency tracker
+/// and the consumer's token, if latencies are recorded for it.  Shared by the consumers
+/// which handle events one at a time on demand (EventPoller and Subscriber).
+pub fn handle_events<T, H>(ring: &RingBuffer<T>, handler: &mut H, from: u64, to: u64, cursor: &Padded64,
+                           signal: &WaitSignal, latency: Option<(&Latency, usize)>) -> Result<u64, H::Error>
+where T: Slot, H: EventHandler<T> + ?Sized {
+    let mask = ring.get_capacity() as u64 - 1;
+    let mut end = to;
+    let mut status = Ok(());
+    for s in from..to {
+        // This is safe because the Producer cannot overwrite the slot before we
+        // increment our cursor, and the borrow ends before that
+        let event = unsafe { &ring.get((s & mask) as usize, 1)[0] };
+        status = handler.on_event(event, s, s + 1 == to);
+        if status.is_err() {
+            end = s + 1;
+            break;
+        }
+    }
+
+    // Must be recorded before the producer is allowed to reuse the stamps
+    if let Some((latency, token)) = latency {
+        latency.record(token, from, end);
+    }
+    cursor.store(end);
+    signal.notify();   // wake the producer and any blocked EPs which depend on us
+
+    match status {
+        Ok(_) => Ok(end),
+        Err(e) => Err(e)
+    }
+}
+
+/// Returns true if every one of `deps` has stopped
+///
+/// The root cursor (the writer) counts as stopped once the Turbine is halted.
+pub fn dependencies_stopped(deps: &[usize], stopped: &[AtomicBool]) -> bool {
+    deps.iter().all(|&dep| dep == 0 || stopped[dep].load(Ordering::SeqCst))
+}
+
+/// Per-slot publication markers used when several producers share one ring
+///
+/// With a single producer the root cursor doubles as the "published" marker,
+/// since slots are always written in order.  Multiple producers claim sequences
+/// out of order, so the root cursor only records what has been *claimed*.  Each
+/// slot therefore records the sequence (plus one) that was last published into
+/// it, and EventProcessors use these markers to find the highest contiguous
+/// sequence that is safe to read.
+pub struct AvailableBuffer {
+    markers: Vec<AtomicNum<u64>>,
+    mask: u64
+}
+
+impl AvailableBuffer {
+    pub fn new(size: usize) -> AvailableBuffer {
+        AvailableBuffer {
+            markers: (0..size).map(|_| AtomicNum::new(0)).collect(),
+            mask: (size - 1) as u64
+        }
+    }
+
+    /// Mark `sequence` as published
+    #[inline]
+    pub fn set(&self, sequence: u64) {
+        self.markers[(sequence & self.mask) as usize].store(sequence + 1, SeqCst);
+    }
+
+    /// Returns true if `sequence` (and not an earlier lap of the same slot) has been published
+    #[inline]
+    pub fn is_available(&self, sequence: u64) -> bool {
+        self.markers[(sequence & self.mask) as usize].load(SeqCst) == sequence + 1
+    }
+
+    /// Find the end of the contiguous run of published sequences starting at `from`
+    ///
+    /// Scans `[from, to)` and returns the first sequence which has not been published,
+    /// or `to` if the entire range is available.  Like the cursors, the returned value
+    /// is exclusive.
+    pub fn highest_published(&self, from: u64, to: u64) -> u64 {
+        let mut sequence = from;
+        while sequence < to {
+            if !self.is_available(sequence) {
+                debug!("              Sequence {} claimed but not yet published", sequence);
+                return sequence;
+            }
+            sequence += 1;
+        }
+        to
+    }
+}
+
+/// A cloneable handle which allows several threads to write into the same Turbine
+///
+/// Each write claims a sequence with an atomic fetch-add on the root cursor, waits
+/// until the slot has been released by every EventProcessor, writes the data and then
+/// flags the slot as published.  MultiProducers are obtained from a Turbine which was
+/// created with `Turbine::new_multi_producer()`.
+pub struct MultiProducer<T> {
+    ring: Arc<RingBuffer<T>>,
+    cursors: Arc<Vec<Padded64>>,
+    available: Arc<AvailableBuffer>,
+    dynamic: Arc<DynamicCursors>,
+    gating_cache: Arc<Padded64>,
+    signal: Arc<WaitSignal>,
+    backpressure: Arc<Backpressure>,
+    latency: Option<Arc<Latency>>,
+    parked_send: Option<Waiting>,
+    size: u64,
+    mask: u64
+}
+
+impl<T> Clone for MultiProducer<T> {
+    fn clone(&self) -> MultiProducer<T> {
+        MultiProducer::<T> {
+            ring: self.ring.clone(),
+            cursors: self.cursors.clone(),
+            available: self.available.clone(),
+            dynamic: self.dynamic.clone(),
+            gating_cache: self.gating_cache.clone(),
+            signal: self.signal.clone(),
+            backpressure: self.backpressure.clone(),
+            latency: self.latency.clone(),
+            parked_send: None,
+            size: self.size,
+            mask: self.mask
+        }
+    }
+}
+
+impl<T: Slot> MultiProducer<T> {
+
+    /// Instantiate a new MultiProducer.  This is for internal use only, see `Turbine::producer()`
+    pub fn new(ring: Arc<RingBuffer<T>>, cursors: Arc<Vec<Padded64>>, available: Arc<AvailableBuffer>,
+               dynamic: Arc<DynamicCursors>, signal: Arc<WaitSignal>, backpressure: Arc<Backpressure>,
+               latency: Option<Arc<Latency>>) -> MultiProducer<T> {
+        let size = ring.get_capacity() as u64;
+        MultiProducer::<T> {
+            ring: ring,
+            cursors: cursors,
+            available: available,
+            dynamic: dynamic,
+            gating_cache: Arc::new(Padded64::new(0)),
+            signal: signal,
+            backpressure: backpressure,
+            latency: latency,
+            parked_send: None,
+            size: size,
+            mask: size - 1
+        }
+    }
+
+    /// Write data into Turbine
+    ///
+    /// Claims the next sequence, busy-spins until the slot is free and then publishes it.
+    /// Writes from a single MultiProducer are seen by EventProcessors in the order they
+    /// were made, but there is no ordering guarantee between different producers.
+    ///
+    ///# Example
+    ///
+    ///```
+    ///let mut t: Turbine<TestSlot> = Turbine::new_multi_producer(1024);
+    ///let e1 = t.ep_new().unwrap();
+    ///let event_processor = t.ep_finalize(e1).unwrap();
+    ///
+    ///let producer = t.producer().unwrap();
+    ///let p2 = producer.clone();
+    ///thread::spawn(move|| {
+    ///    p2.write(Slot::new());
+    ///});
+    ///producer.write(Slot::new());
+    ///```
+    pub fn write(&self, data: T) {
+        let mut slot = self.claim();
+        *slot = data;
+        slot.publish();
+        debug!("Write complete.")
+    }
+
+    /// Claim the next sequence for writing in place
+    ///
+    /// See `Turbine::claim()`.  The slot is published when the returned Claim is dropped.
+    /// Producers should publish promptly: EventProcessors cannot read past a claimed but
+    /// unpublished slot, even if later sequences have been published by other producers.
+    pub fn claim(&self) -> Claim<T> {
+        let sequence = self.cursors.as_slice()[0].add(1);
+
+        // Busy spin
+        if !self.can_write(sequence) {
+            let mut waiting = Waiting::new(self.backpressure.clone(), sequence, self.cursors.as_slice(), &self.dynamic);
+            while !self.can_write(sequence) {
+                waiting.spin();
+            }
+        }
+
+        let write_pos = sequence & self.mask;
+        debug!("claimed sequence {}, writing to {}", sequence, write_pos);
+
+        let slot = unsafe { self.ring.get_mut(write_pos as usize) };
+        Claim::new(slot, sequence, Publisher::Available(&*self.available), &*self.signal,
+                   self.latency.as_ref().map(|l| &**l))
+    }
+
+    /// Attempt to write data into Turbine without waiting
+    ///
+    /// See `Turbine::try_write()`.  If the ring is full, the data is handed back in the Err.
+    pub fn try_write(&self, data: T) -> Result<(), T> {
+        match self.try_claim() {
+            Ok(mut slot) => {
+                *slot = data;
+                slot.publish();
+                Ok(())
+            },
+            Err(_) => Err(data)
+        }
+    }
+
+    /// Attempt to claim the next sequence without waiting
+    ///
+    /// Returns `TurbineError::RingFull` if the ring is full, or `TurbineError::Halted` if
+    /// the Turbine has been halted.  Unlike `claim()`, the sequence is only taken (with a
+    /// compare-and-swap on the root cursor) once the slot is known to be free, so a failed
+    /// attempt leaves no gap behind.
+    pub fn try_claim(&self) -> Result<Claim<T>, TurbineError> {
+        if self.signal.is_alerted() {
+            return Err(TurbineError::Halted);
+        }
+
+        let root = &self.cursors.as_slice()[0];
+        let mut sequence = root.load();
+
+        loop {
+            if !self.can_write(sequence) {
+                debug!("Ring full, cannot claim {}", sequence);
+                return Err(TurbineError::RingFull);
+            }
+
+            let previous = root.compare_and_swap(sequence, sequence + 1);
+            if previous == sequence {
+                break;
+            }
+            sequence = previous;   // Another producer got there first, try again
+        }
+
+        let slot = unsafe { self.ring.get_mut((sequence & self.mask) as usize) };
+        Ok(Claim::new(slot, sequence, Publisher::Available(&*self.available), &*self.signal,
+                      self.latency.as_ref().map(|l| &**l)))
+    }
+
+    /// The number of slots which could currently be claimed without waiting
+    ///
+    /// This is a snapshot; by the time it is used, other producers may have claimed
+    /// slots or EventProcessors may have released some.
+    pub fn remaining_capacity(&self) -> usize {
+        let claimed = self.cursors.as_slice()[0].load();
+        let min_cursor = minimum_cursor(self.cursors.as_slice(), claimed);
+        let min_cursor = self.dynamic.minimum(min_cursor, None);
+        (self.size - min(claimed - min_cursor, self.size)) as usize
+    }
+
+    /// Claim up to `n` contiguous sequences for writing in place
+    ///
+    /// See `Turbine::claim_batch()`.  All of the sequences are claimed with a single
+    /// fetch-add, and published together when the returned BatchClaim is dropped.
+    pub fn claim_batch(&self, n: usize) -> BatchClaim<T> {
+        let n = min(n as u64, self.size);
+        let sequence = self.cursors.as_slice()[0].add(n);
+
+        // Busy spin until the last slot of the batch is free
+        if n > 0 && !self.can_write(sequence + n - 1) {
+            let mut waiting = Waiting::new(self.backpressure.clone(), sequence, self.cursors.as_slice(), &self.dynamic);
+            while !self.can_write(sequence + n - 1) {
+                waiting.spin();
+            }
+        }
+
+        debug!("claimed sequences {} to {}", sequence, sequence + n);
+        let (first, second) = unsafe { self.ring.get_wrapping_mut((sequence & self.mask) as usize, n as usize) };
+        BatchClaim::new(first, second, sequence, Publisher::Available(&*self.available), &*self.signal,
+                        self.latency.as_ref().map(|l| &**l))
+    }
+
+    /// Forget the cached gating position, so the next claim checks every consumer again
+    ///
+    /// Called after a Subscriber has been attached, see `Turbine::subscribe_from()`.
+    pub fn reset_gating(&self) {
+        self.gating_cache.store(0);
+    }
+
+    /// Check if the slot for `sequence` has been released by every EventProcessor
+    ///
+    /// The minimum EP cursor is cached (and shared between clones) so that most
+    /// writes only need to load a single atomic.
+    fn can_write(&self, sequence: u64) -> bool {
+        if sequence < self.gating_cache.load() + self.size {
+            return true;
+        }
+
+        let min_cursor = minimum_cursor(self.cursors.as_slice(), sequence);
+        let min_cursor = self.dynamic.minimum(min_cursor, None);
+        self.gating_cache.store(min_cursor);
+
+        debug!("sequence: {}, min_cursor: {}", sequence, min_cursor);
+        sequence < min_cursor + self.size
+    }
+}
+
+
+/// A producer which can be used as a futures `Sink`, see `start_send()`
+pub trait SinkProducer<T> {
+    /// Attempt to claim the next slot without waiting
+    fn try_claim_slot(&mut self) -> Result<Claim<T>, TurbineError>;
+
+    /// The signal which wakes parked tasks
+    fn signal(&self) -> &WaitSignal;
+
+    /// Start tracking a send which found the ring full
+    fn waiting(&self) -> Waiting;
+
+    /// The send which is currently parked, if any
+    fn parked_send(&mut self) -> &mut Option<Waiting>;
+}
+
+/// The `Sink::start_send()` shared by Turbine and MultiProducer
+///
+/// If the ring is full, the current task is parked and the item is handed back with
+/// `AsyncSink::NotReady`.  The parked send is tracked with a `Waiting`, so it shows up
+/// in the producer's statistics (and stall callback) like a blocking `write()` once it
+/// has gone through.
+pub fn start_send<T, P>(producer: &mut P, item: T) -> StartSend<T, TurbineError>
+where T: Slot, P: SinkProducer<T> {
+    // Dropping the Waiting records how long the send was parked
+    let waiting = producer.parked_send().take();
+
+    let mut parked = false;
+    loop {
+        match producer.try_claim_slot() {
+            Ok(mut slot) => {
+                *slot = item;
+                slot.publish();
+                return Ok(AsyncSink::Ready);
+            },
+            Err(TurbineError::RingFull) if parked => break,
+            Err(TurbineError::RingFull) => {},
+            Err(e) => return Err(e)
+        }
+
+        // Look again once parked, in case a cursor moved in between
+        producer.signal().park();
+        parked = true;
+    }
+
+    let waiting = match waiting {
+        Some(mut waiting) => {
+            waiting.spin();
+            waiting
+        },
+        None => producer.waiting()
+    };
+    *producer.parked_send() = Some(waiting);
+    Ok(AsyncSink::NotReady(item))
+}
+
+impl<T: Slot> SinkProducer<T> for MultiProducer<T> {
+    fn try_claim_slot(&mut self) -> Result<Claim<T>, TurbineError> {
+        self.try_claim()
+    }
+
+    fn signal(&self) -> &WaitSignal {
+        &self.signal
+    }
+
+    fn waiting(&self) -> Waiting {
+        Waiting::new(self.backpressure.clone(), self.cursors.as_slice()[0].load(), self.cursors.as_slice(), &self.dynamic)
+    }
+
+    fn parked_send(&mut self) -> &mut Option<Waiting> {
+        &mut self.parked_send
+    }
+}
+
+/// An async handle for writing into Turbine
+///
+/// Instead of busy-spinning while the ring is full, `start_send()` parks the current
+/// task and returns `AsyncSink::NotReady`.  The task is woken once an EventProcessor
+/// advances its cursor, and the time spent parked is counted in the producer's
+/// statistics like a waiting `write()`.  Each item is published as soon as it is accepted, so
+/// `poll_complete()` never has anything left to do.  Sending to a halted Turbine
+/// fails with `TurbineError::Halted`.
+///
+///# Example
+///
+///```
+///let producer = t.producer().unwrap();
+///let items = stream::iter_ok::<_, TurbineError>(events);
+///let (producer, _) = producer.send_all(items).wait().unwrap();
+///```
+impl<T: Slot> Sink for MultiProducer<T> {
+    type SinkItem = T;
+    type SinkError = TurbineError;
+
+    fn start_send(&mut self, item: T) -> StartSend<T, TurbineError> {
+        start_send(self, item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), TurbineError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::AvailableBuffer;
+
+    #[test]
+    fn available_contiguous() {
+        let a = AvailableBuffer::new(8);
+        a.set(0);
+        a.set(1);
+        a.set(3);
+        assert!(a.highest_published(0, 4) == 2);
+
+        a.set(2);
+        assert!(a.highest_published(0, 4) == 4);
+    }
+
+    #[test]
+    fn available_ignores_previous_lap() {
+        let a = AvailableBuffer::new(4);
+        a.set(1);
+        assert!(a.is_available(1) == true);
+        assert!(a.is_available(5) == false);
+
+        a.set(5);
+        assert!(a.is_available(5) == true);
+        assert!(a.is_available(1) == false);
+    }
+}
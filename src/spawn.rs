@@ -0,0 +1,74 @@
+use std::io;
+use std::thread::{self, JoinHandle};
+use ringbuffer::Slot;
+use waitstrategy::WaitStrategy;
+use handler::EventHandler;
+use eventprocessor::EventProcessor;
+use affinity;
+use exception::ExceptionHandler;
+
+/// A boxed ExceptionHandler, so each thread can be given its own
+pub type BoxedExceptionHandler<T> = Box<ExceptionHandler<T> + Send>;
+
+/// A thread running an EventProcessor, which owns the processor's EventHandler
+///
+/// Obtained from `EventProcessor::spawn()`.  The handler is handed back by `join()`
+/// once the EP has stopped, so any state it accumulated can be inspected (or reused),
+/// along with the EP's final cursor.
+pub struct ProcessorThread<T, H: EventHandler<T>> {
+    handle: JoinHandle<(H, Result<u64, H::Error>)>,
+    name: String
+}
+
+impl<T, H: EventHandler<T>> ProcessorThread<T, H> {
+
+    /// The name of the thread, `turbine-ep-<token>`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Wait for the EventProcessor to stop
+    ///
+    /// Returns the handler and the EP's final cursor if the EP stopped after a halt, or
+    /// the error returned by the handler which stopped it.  Panics if the handler panicked.
+    pub fn join(self) -> Result<(H, u64), H::Error> {
+        match self.handle.join() {
+            Ok((handler, Ok(cursor))) => Ok((handler, cursor)),
+            Ok((_, Err(e))) => Err(e),
+            Err(_) => panic!("{} panicked", self.name)
+        }
+    }
+}
+
+/// Spawn a named thread which drives `ep` with `handler`.  See `EventProcessor::spawn()`.
+///
+/// If `cores` is given, the thread pins itself to them before starting the handler.
+/// Pinning is only a performance hint, so a failure is logged rather than stopping the EP.
+/// If `exceptions` is given, panics in the handler are passed to it.
+/// Returns the error from the OS if the thread could not be created, in which case
+/// `ep` and `handler` are dropped.
+pub fn spawn<T, H, W>(ep: EventProcessor<T>, mut handler: H, cores: Option<Vec<usize>>,
+                      exceptions: Option<BoxedExceptionHandler<T>>) -> io::Result<ProcessorThread<T, H>>
+where T: Slot + 'static, H: EventHandler<T> + Send + 'static, H::Error: Send + 'static,
+      W: WaitStrategy + 'static {
+    let name = format!("turbine-ep-{}", ep.token());
+
+    let handle = try!(thread::Builder::new().name(name.clone()).spawn(move|| {
+        if let Some(cores) = cores {
+            if let Err(e) = affinity::pin_current_thread(&cores) {
+                warn!("Could not pin EventProcessor {} to cores {:?}: {}", ep.token(), cores, e);
+            }
+        }
+
+        let result = match exceptions {
+            Some(mut x) => ep.start_with_exception_handler::<_, _, W>(&mut handler, &mut x),
+            None => ep.start_with_handler::<_, W>(&mut handler)
+        };
+        (handler, result)
+    }));
+
+    Ok(ProcessorThread {
+        handle: handle,
+        name: name
+    })
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::cmp::min;
+use paddedatomics::Padded64;
+use backpressure::{Backpressure, Bottleneck};
+use latency::{Latency, LatencyHistogram};
+use subscriber::DynamicCursors;
+
+/// A snapshot of one EventProcessor (or WorkerPool)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessorStats {
+    /// The token returned by `Turbine::ep_new()`
+    pub token: usize,
+
+    /// The name given with `Turbine::ep_name()`, or `turbine-ep-<token>`
+    pub name: String,
+
+    /// The next sequence this EP will process
+    pub cursor: u64,
+
+    /// How many events the EP is behind the producer
+    pub lag: u64,
+
+    /// True once the EP has stopped processing
+    pub stopped: bool,
+
+    /// Publish-to-handle latencies, if enabled with `Turbine::track_latency()`
+    pub latency: Option<LatencyHistogram>
+}
+
+/// A snapshot of one attached Subscriber
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriberStats {
+    /// The id returned by `Subscriber::id()`
+    pub id: usize,
+
+    /// The next sequence this Subscriber will handle
+    pub cursor: u64,
+
+    /// How many events the Subscriber is behind the producer
+    pub lag: u64
+}
+
+/// How often the producer had to wait for EPs to release slots
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProducerStats {
+    /// The number of writes which found the ring full and had to wait
+    pub waited_writes: u64,
+
+    /// The number of times those writes checked for a free slot while waiting
+    pub spin_iterations: u64,
+
+    /// The total time spent waiting, in nanoseconds
+    pub wait_ns: u64,
+
+    /// The slowest consumer (EP or Subscriber) the last time a write had to wait
+    pub bottleneck: Option<Bottleneck>
+}
+
+/// A snapshot of a Turbine's cursors
+///
+/// The cursors are read one after the other while the pipeline keeps running, so the
+/// snapshot is not atomic: an EP may appear slightly ahead of or behind its dependencies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurbineStats {
+    /// The number of events written so far.  In multi-producer mode this counts claimed
+    /// sequences, some of which may not have been published yet.
+    pub producer_cursor: u64,
+
+    /// The number of slots in the ring buffer
+    pub capacity: usize,
+
+    /// The number of slots which have been written but not yet released by every EP
+    /// and Subscriber
+    pub occupancy: usize,
+
+    /// One entry per EP, in token order
+    pub processors: Vec<ProcessorStats>,
+
+    /// One entry per attached Subscriber, in the order they were attached
+    pub subscribers: Vec<SubscriberStats>,
+
+    /// Backpressure seen by the producer(s)
+    pub producer: ProducerStats
+}
+
+/// A cloneable handle for reading TurbineStats from any thread
+///
+/// Obtained from `Turbine::stats_handle()` once the graph has been finalized.
+#[derive(Clone)]
+pub struct StatsHandle {
+    cursors: Arc<Vec<Padded64>>,
+    stopped: Arc<Vec<AtomicBool>>,
+    names: Arc<Vec<String>>,
+    dynamic: Arc<DynamicCursors>,
+    backpressure: Arc<Backpressure>,
+    latency: Option<Arc<Latency>>,
+    capacity: usize
+}
+
+impl StatsHandle {
+    /// Instantiate a new StatsHandle.  This is for internal use only.
+    pub fn new(cursors: Arc<Vec<Padded64>>, stopped: Arc<Vec<AtomicBool>>, names: Arc<Vec<String>>,
+               dynamic: Arc<DynamicCursors>, backpressure: Arc<Backpressure>, latency: Option<Arc<Latency>>,
+               capacity: usize) -> StatsHandle {
+        StatsHandle {
+            cursors: cursors,
+            stopped: stopped,
+            names: names,
+            dynamic: dynamic,
+            backpressure: backpressure,
+            latency: latency,
+            capacity: capacity
+        }
+    }
+
+    /// Take a snapshot of the Turbine's cursors
+    pub fn snapshot(&self) -> TurbineStats {
+        let cursors = self.cursors.as_slice();
+        let producer_cursor = cursors[0].load();
+
+        let mut processors = Vec::with_capacity(self.names.len());
+        let mut min_cursor = producer_cursor;
+        for (token, name) in self.names.iter().enumerate() {
+            let cursor = cursors[token + 1].load();
+            min_cursor = min(min_cursor, cursor);
+            processors.push(ProcessorStats {
+                token: token,
+                name: name.clone(),
+                cursor: cursor,
+                lag: producer_cursor - min(cursor, producer_cursor),
+                stopped: self.stopped.as_slice()[token + 1].load(Ordering::SeqCst),
+                latency: self.latency.as_ref().and_then(|l| l.histogram(token))
+            });
+        }
+
+        // Subscribers gate the producer just like EPs, see DynamicCursors
+        let mut subscribers = vec![];
+        for (id, cursor) in self.dynamic.positions().into_iter() {
+            min_cursor = min(min_cursor, cursor);
+            subscribers.push(SubscriberStats {
+                id: id,
+                cursor: cursor,
+                lag: producer_cursor - min(cursor, producer_cursor)
+            });
+        }
+
+        TurbineStats {
+            producer_cursor: producer_cursor,
+            capacity: self.capacity,
+            occupancy: min((producer_cursor - min_cursor) as usize, self.capacity),
+            processors: processors,
+            subscribers: subscribers,
+            producer: ProducerStats {
+                waited_writes: self.backpressure.waited_writes(),
+                spin_iterations: self.backpressure.spin_iterations(),
+                wait_ns: self.backpressure.wait_ns(),
+                bottleneck: self.backpressure.bottleneck()
+            }
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::cmp::min;
+use std::thread;
+use waitstrategy::{WaitStrategy, WaitSignal};
+use paddedatomics::Padded64;
+use ringbuffer::{RingBuffer, Slot};
+use sequencer::{self, AvailableBuffer, minimum_cursor};
+use handler::EventHandler;
+use poller::PollState;
+use error::TurbineError;
+
+/// The cursors of Subscribers attached after the graph was finalized
+///
+/// Producers gate on these in addition to the fixed cursor vector.  Attaching or
+/// detaching replaces the whole list, so producers only take the lock to clone an Arc,
+/// and only on the slow path of a write (once their cached gating position is used up).
+/// While nothing is attached, the list costs producers a single atomic load.
+pub struct DynamicCursors {
+    cursors: Mutex<Arc<Vec<(usize, Arc<Padded64>)>>>,
+    count: AtomicUsize,
+    next_id: AtomicUsize
+}
+
+impl DynamicCursors {
+    pub fn new() -> DynamicCursors {
+        DynamicCursors {
+            cursors: Mutex::new(Arc::new(vec![])),
+            count: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0)
+        }
+    }
+
+    /// Start gating producers on `cursor`, returning an id for `detach()`
+    pub fn attach(&self, cursor: Arc<Padded64>) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut cursors = self.cursors.lock().unwrap();
+        let mut updated = (**cursors).clone();
+        updated.push((id, cursor));
+        self.count.store(updated.len(), Ordering::SeqCst);
+        *cursors = Arc::new(updated);
+        id
+    }
+
+    /// Stop gating producers on the cursor attached as `id`
+    pub fn detach(&self, id: usize) {
+        let mut cursors = self.cursors.lock().unwrap();
+        let updated: Vec<(usize, Arc<Padded64>)> = cursors.iter()
+            .filter(|&&(i, _)| i != id)
+            .cloned()
+            .collect();
+        self.count.store(updated.len(), Ordering::SeqCst);
+        *cursors = Arc::new(updated);
+    }
+
+    /// The id and position of every attached cursor
+    pub fn positions(&self) -> Vec<(usize, u64)> {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            return vec![];
+        }
+
+        let cursors = self.cursors.lock().unwrap().clone();
+        cursors.iter().map(|&(id, ref cursor)| (id, cursor.load())).collect()
+    }
+
+    /// Find the minimum attached cursor (ignoring `except`), or `default` if it is lower
+    pub fn minimum(&self, default: u64, except: Option<usize>) -> u64 {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            return default;
+        }
+
+        let cursors = self.cursors.lock().unwrap().clone();
+        let mut min_cursor = default;
+        for &(id, ref cursor) in cursors.iter() {
+            if Some(id) != except {
+                min_cursor = min(min_cursor, cursor.load());
+            }
+        }
+        min_cursor
+    }
+}
+
+/// Where a new Subscriber starts, see `Turbine::subscribe_from()`
+///
+/// Wherever it starts, a Subscriber is only handed events which every mutable EP has
+/// already released, see `Subscriber`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartAt {
+    /// The oldest event still in the ring, i.e. up to one ring's worth of events back
+    ///
+    /// In multi-producer mode this is limited to the oldest event another consumer has
+    /// not handled yet, since a producer may already be overwriting older slots.
+    Oldest,
+
+    /// The next event to be written
+    Latest,
+
+    /// A specific sequence, which must lie between `Oldest` and `Latest`
+    Sequence(u64)
+}
+
+/// A consumer attached to a running Turbine with `Turbine::subscribe()`
+///
+/// EventProcessors are fixed once the graph has been finalized.  A Subscriber can be
+/// added (and removed) at any time, e.g. to turn on an audit trail during an incident.
+/// It sees every event from its starting point onwards (see `StartAt`), and gates the
+/// producer just like an EP: the producer never overwrites an event the Subscriber has not handled.
+///
+/// Subscribers depend on the producer, and nothing can depend on them.  If the graph
+/// has EPs marked with `Turbine::ep_mutable()`, Subscribers depend on those EPs instead,
+/// so an event is never read while a mutable EP may still be modifying it.  Dropping
+/// a Subscriber (or calling `detach()`) detaches it, and the producer stops waiting for
+/// it.  A Subscriber must therefore be kept alive while it is in use, and should keep
+/// up with the producer, or be detached.
+///
+///##Example
+///
+///```
+///let subscriber = t.subscribe().unwrap();
+///let audit = thread::spawn(move|| {
+///    let mut auditor = Auditor::new();
+///    subscriber.start_with_handler::<_, BlockingWait>(&mut auditor)
+///});
+///```
+pub struct Subscriber<T> {
+    ring: Arc<RingBuffer<T>>,
+    cursors: Arc<Vec<Padded64>>,
+    stopped: Arc<Vec<AtomicBool>>,
+    deps: Vec<usize>,
+    available: Option<Arc<AvailableBuffer>>,
+    signal: Arc<WaitSignal>,
+    dynamic: Arc<DynamicCursors>,
+    cursor: Arc<Padded64>,
+    id: usize
+}
+
+impl<T: Slot> Subscriber<T> {
+
+    /// Instantiate a new Subscriber and attach its cursor.  This is for internal use only.
+    ///
+    /// `deps` are the indices of the cursors the Subscriber reads behind: the cursors of
+    /// the mutable EPs, or just the root cursor if there are none.
+    ///
+    /// The caller must make the producers drop any gating position they cached before
+    /// the Subscriber was attached.  `shared` is true in multi-producer mode, where a
+    /// producer may already have passed its check with such a position.
+    ///
+    /// Returns `TurbineError::SequenceUnavailable` if `start` is an explicit sequence
+    /// which the producer may already have overwritten, or which has not been claimed yet.
+    pub fn new(ring: Arc<RingBuffer<T>>, cursors: Arc<Vec<Padded64>>, stopped: Arc<Vec<AtomicBool>>,
+               deps: Vec<usize>, available: Option<Arc<AvailableBuffer>>, signal: Arc<WaitSignal>,
+               dynamic: Arc<DynamicCursors>, start: StartAt, shared: bool) -> Result<Subscriber<T>, TurbineError> {
+        let latest = cursors.as_slice()[0].load();
+        let oldest = latest - min(latest, ring.get_capacity() as u64);
+        let requested = match start {
+            StartAt::Oldest => oldest,
+            StartAt::Latest => latest,
+            StartAt::Sequence(sequence) if sequence > latest || sequence < oldest => {
+                return Err(TurbineError::SequenceUnavailable(sequence));
+            },
+            StartAt::Sequence(sequence) => sequence
+        };
+
+        let cursor = Arc::new(Padded64::new(requested));
+        let id = dynamic.attach(cursor.clone());
+        let subscriber = Subscriber::<T> {
+            ring: ring,
+            cursors: cursors,
+            stopped: stopped,
+            deps: deps,
+            available: available,
+            signal: signal,
+            dynamic: dynamic,
+            cursor: cursor,
+            id: id
+        };
+
+        // Other producers may be writing with a position they cached before we were
+        // attached.  That position can't be ahead of any other consumer, so everything
+        // from the slowest one on is safe.
+        if shared {
+            let retained = subscriber.dynamic.minimum(minimum_cursor(subscriber.cursors.as_slice(), latest), Some(id));
+            if requested < retained {
+                match start {
+                    StartAt::Sequence(sequence) => return Err(TurbineError::SequenceUnavailable(sequence)),
+                    _ => subscriber.cursor.store(retained)
+                }
+            }
+        }
+        Ok(subscriber)
+    }
+
+    /// The next sequence this Subscriber will handle
+    pub fn sequence(&self) -> u64 {
+        self.cursor.load()
+    }
+
+    /// The id which identifies this Subscriber in `TurbineStats::subscribers`
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Stop gating the producer.  Equivalent to dropping the Subscriber.
+    pub fn detach(self) {}
+
+    /// Handle every event which is currently available, without waiting
+    ///
+    /// Behaves like `EventPoller::poll()`.  It only reports `PollState::Gating` while
+    /// published events are held back by a mutable EP.
+    pub fn poll<H>(&mut self, handler: &mut H) -> Result<PollState, H::Error>
+    where H: EventHandler<T> + ?Sized {
+        let sequence = self.cursor.load();
+        let available = self.available_from(sequence, 18446744073709551615);
+        if available == sequence {
+            return match self.published(sequence) {
+                true => Ok(PollState::Gating),
+                false => Ok(PollState::Idle)
+            };
+        }
+
+        try!(sequencer::handle_events(&self.ring, handler, sequence, available, &self.cursor, &self.signal, None));
+        Ok(PollState::Processing)
+    }
+
+    /// Wait for events and pass each one to `handler`, until the Turbine is halted
+    ///
+    /// Like `EventProcessor::start_with_handler()`, this drains everything published
+    /// before the halt and then returns the final cursor position, or returns the first
+    /// Err from the handler.
+    pub fn start_with_handler<H, W: WaitStrategy>(&self, handler: &mut H) -> Result<u64, H::Error>
+    where H: EventHandler<T> + ?Sized {
+        let wait_strategy: W = WaitStrategy::with_signal(self.ring.get_capacity(), self.signal.clone());
+        let deps: Vec<&Padded64> = self.deps.iter().map(|&dep| &self.cursors.as_slice()[dep]).collect();
+
+        handler.on_start();
+        let mut sequence = self.cursor.load();
+        loop {
+            // See EventProcessor::run(), the producer counts as stopped once halted
+            let drained = self.signal.is_alerted()
+                          && sequencer::dependencies_stopped(&self.deps, self.stopped.as_slice());
+
+            let claimed = wait_strategy.wait_for(sequence, &deps);
+            let available = self.available_from(sequence, claimed);
+            if available == sequence {
+                if drained {
+                    break;
+                }
+
+                // See EventProcessor::run(), don't spin on a claimed but unpublished slot
+                if claimed > sequence {
+                    thread::yield_now();
+                }
+                continue;
+            }
+
+            match sequencer::handle_events(&self.ring, handler, sequence, available, &self.cursor, &self.signal, None) {
+                Ok(end) => sequence = end,
+                Err(e) => {
+                    handler.on_shutdown();
+                    return Err(e);
+                }
+            }
+        }
+        handler.on_shutdown();
+        Ok(sequence)
+    }
+
+    /// The end of the run of events starting at `sequence` which have been published and
+    /// released by every mutable EP, up to `limit`
+    fn available_from(&self, sequence: u64, limit: u64) -> u64 {
+        sequencer::available_from(&self.deps, self.cursors.as_slice(), &self.available, sequence, limit)
+    }
+
+    /// Returns true if the producer has published `sequence`
+    fn published(&self, sequence: u64) -> bool {
+        sequencer::available_from(&[0], self.cursors.as_slice(), &self.available, sequence, sequence + 1) > sequence
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        self.dynamic.detach(self.id);
+        debug!("Detached subscriber {}", self.id);
+    }
+}
@@ -1,6 +1,25 @@
 
 use paddedatomics::Padded64;
 use std::cmp::{min};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::mem;
+use futures::task::{self, Task};
+
+/// Number of busy-spins YieldingWait performs before it starts yielding
+const YIELD_SPIN_TRIES: usize = 100;
+
+/// Number of busy-spins SleepingWait performs before it starts yielding
+const SLEEP_SPIN_TRIES: usize = 100;
+
+/// Number of yields SleepingWait performs before it starts sleeping
+const SLEEP_YIELD_TRIES: usize = 100;
+
+/// Initial and maximum sleep used by SleepingWait, in nanoseconds
+const SLEEP_MIN_NS: u32 = 1000;
+const SLEEP_MAX_NS: u32 = 100000;
 
 /// A trait which provides a unified interface to various waiting strategies
 pub trait WaitStrategy {
@@ -8,6 +27,16 @@ pub trait WaitStrategy {
     /// Instantiate a new WaitStrategy. Must provide the size of the underlying buffer
     fn new(ring_size: usize) -> Self;
 
+    /// Instantiate a new WaitStrategy which shares the Turbine's WaitSignal
+    ///
+    /// This is how EventProcessors construct their strategy.  The signal is used to
+    /// wake blocked strategies and to alert them that the Turbine has been halted.
+    /// The default ignores the signal, which means the strategy will not notice
+    /// `Turbine::halt()`; all the built-in strategies override it.
+    fn with_signal(ring_size: usize, _signal: Arc<WaitSignal>) -> Self where Self: Sized {
+        WaitStrategy::new(ring_size)
+    }
+
     /// Get the underlying max buffer capacity
     fn get_ring_size(&self) -> usize;
 
@@ -18,8 +47,167 @@ pub trait WaitStrategy {
     /// of blocking varies depending on the implementation (e.g. busy-wait, sleep, etc).
     ///
     /// This method should return the highest available position in the buffer to
-    /// allow EventProcessors to batch reads
+    /// allow EventProcessors to batch reads.  If the signal has been alerted, the method
+    /// must return immediately; when nothing is available it returns `sequence` itself.
     fn wait_for(&self, sequence: u64, ep: &Vec<&Padded64>) -> u64;
+
+    /// Wait for the requested sequence, giving up after `timeout`
+    ///
+    /// Behaves like `wait_for()`, except that it returns None if no slot became
+    /// available before the timeout elapsed.  This allows EventProcessors to perform
+    /// housekeeping (flushing, heartbeats, etc) on an idle ring.
+    ///
+    /// The default checks the dependencies and yields between checks.  It has no signal,
+    /// so a halt is only noticed once the timeout has elapsed.  All the built-in
+    /// strategies override it.
+    fn wait_for_timeout(&self, sequence: u64, ep: &Vec<&Padded64>, timeout: Duration) -> Option<u64> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(v) = can_read(sequence, ep) {
+                return Some(v);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            thread::yield_now();
+        }
+    }
+}
+
+/// A signal shared between the writer and all EventProcessors of a Turbine
+///
+/// Writers (and EventProcessors, since they are dependencies of one another) call
+/// `notify()` after advancing a cursor.  Blocking strategies park on the condition
+/// variable until notified.  When nobody is parked, `notify()` is a single atomic
+/// load so that spinning strategies do not pay for the lock.
+///
+/// Async tasks (e.g. an EventProcessor polled as a Stream) register themselves with
+/// `park()` instead, and are woken by the next `notify()`.
+///
+/// The signal also carries the Turbine's alert flag, which is raised by
+/// `Turbine::halt()` to tell every WaitStrategy to stop waiting.
+pub struct WaitSignal {
+    lock: Mutex<()>,
+    condvar: Condvar,
+    waiters: AtomicUsize,
+    tasks: Mutex<Vec<Task>>,
+    parked: AtomicUsize,
+    alerted: AtomicBool
+}
+
+impl WaitSignal {
+    pub fn new() -> WaitSignal {
+        WaitSignal {
+            lock: Mutex::new(()),
+            condvar: Condvar::new(),
+            waiters: AtomicUsize::new(0),
+            tasks: Mutex::new(vec![]),
+            parked: AtomicUsize::new(0),
+            alerted: AtomicBool::new(false)
+        }
+    }
+
+    /// Raise the alert flag and wake every blocked waiter
+    pub fn alert(&self) {
+        self.alerted.store(true, Ordering::SeqCst);
+        self.notify();
+    }
+
+    /// Returns true once `alert()` has been called
+    #[inline]
+    pub fn is_alerted(&self) -> bool {
+        self.alerted.load(Ordering::SeqCst)
+    }
+
+    /// Wake every blocked waiter and parked task, if there are any
+    #[inline]
+    pub fn notify(&self) {
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.condvar.notify_all();
+        }
+        if self.parked.load(Ordering::SeqCst) > 0 {
+            self.wake_tasks();
+        }
+    }
+
+    /// Block the current thread until `condition` returns true
+    ///
+    /// The condition is checked again after every `notify()`, so it must only depend on
+    /// state which is followed by a notify when it changes (e.g. cursors and stopped flags).
+    pub fn block_until<F: Fn() -> bool>(&self, condition: F) {
+        if condition() {
+            return;
+        }
+
+        // See BlockingWait::wait_until(), the waiter is counted before re-checking
+        let mut guard = self.lock.lock().unwrap();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        while !condition() {
+            guard = self.condvar.wait(guard).unwrap();
+        }
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Register the current async task to be woken by the next `notify()`
+    ///
+    /// The caller must check its condition again after parking, since a cursor may
+    /// have moved (and notified) just before the task was registered.  A task which
+    /// parks again before it has been woken replaces its earlier registration, so a
+    /// task polled repeatedly doesn't pile up in the list.  Must be called from
+    /// within a task.
+    pub fn park(&self) {
+        let mut tasks = self.tasks.lock().unwrap();
+        match tasks.iter().position(|t| t.will_notify_current()) {
+            Some(i) => tasks[i] = task::current(),
+            None => tasks.push(task::current())
+        }
+        self.parked.store(tasks.len(), Ordering::SeqCst);
+    }
+
+    fn wake_tasks(&self) {
+        let tasks = {
+            let mut tasks = self.tasks.lock().unwrap();
+            self.parked.store(0, Ordering::SeqCst);
+            mem::replace(&mut *tasks, vec![])
+        };
+        for task in tasks.into_iter() {
+            task.notify();
+        }
+    }
+}
+
+/// Check if `sequence` can be read, returning the minimum dependency cursor if so
+///
+/// Shared by all the WaitStrategy implementations.
+fn can_read(sequence: u64, deps: &Vec<&Padded64>) -> Option<u64> {
+    let mut min_cursor = 18446744073709551615;
+
+    for v in deps.iter() {
+        let cursor = v.load();
+        debug!("					cursor: {}", cursor);
+
+        // EventProcessors never pass their dependencies, but pool workers may claim
+        // sequences ahead of them
+        if sequence >= cursor {
+            debug!("					Same as dep cursor, abort!");
+            return None;	// at (or past) the position of a dependency. we can't move
+        }
+        min_cursor = min(min_cursor, cursor);
+        debug!("					dep cursor: {}, sequence: {}", cursor, sequence);
+        debug!("					min_cursor: {}", min_cursor);
+
+    }
+    Some(min_cursor)
+}
+
+/// Returns true if `deadline` is set and has passed
+#[inline]
+fn expired(deadline: &Option<Instant>) -> bool {
+    match *deadline {
+        Some(d) => Instant::now() >= d,
+        None => false
+    }
 }
 
 /// An implementation of WaitStrategy that busy-spins while waiting
@@ -27,34 +215,229 @@ pub trait WaitStrategy {
 /// This strategy should have the best perforamnce and keep caches hot, but will chew
 /// CPU while there is no work to be done.
 pub struct BusyWait {
-    ring_size: usize
+    ring_size: usize,
+    signal: Arc<WaitSignal>
+}
+
+impl WaitStrategy for BusyWait {
+    fn new(ring_size: usize) -> BusyWait {
+        BusyWait::with_signal(ring_size, Arc::new(WaitSignal::new()))
+    }
+
+    fn with_signal(ring_size: usize, signal: Arc<WaitSignal>) -> BusyWait {
+        BusyWait {
+            ring_size: ring_size,
+            signal: signal
+        }
+    }
+
+    fn get_ring_size(&self) -> usize {
+        self.ring_size
+    }
+
+    fn wait_for(&self, sequence: u64, deps: &Vec<&Padded64>) -> u64 {
+        self.wait_until(sequence, deps, None).unwrap()
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &Vec<&Padded64>, timeout: Duration) -> Option<u64> {
+        self.wait_until(sequence, deps, Some(Instant::now() + timeout))
+    }
 }
 
 impl BusyWait {
-    fn can_read(&self, sequence: u64, deps: &Vec<&Padded64>) -> Option<u64> {
-        let mut min_cursor = 18446744073709551615;
+    fn wait_until(&self, sequence: u64, deps: &Vec<&Padded64>, deadline: Option<Instant>) -> Option<u64> {
+        let available: Option<u64>;
+        debug!("					Waiting for: {}", sequence);
+        loop {
+            match can_read(sequence, deps) {
+                Some(v) => {
+                    available = Some(v);
+                    break
+                },
+                None if self.signal.is_alerted() => {
+                    available = Some(sequence);
+                    break
+                },
+                None if expired(&deadline) => {
+                    available = None;
+                    break
+                },
+                None => {}
+            }
+        }
+        debug!("					Wait done, returning {:?}", available);
+        available
+    }
+}
+
+/// An implementation of WaitStrategy that blocks on a condition variable while waiting
+///
+/// This strategy uses no CPU while the ring is idle, at the cost of a lock and a
+/// thread wake-up for the first event after a quiet period.  Use it for pipelines
+/// with low or bursty traffic where latency is less important than CPU usage.
+///
+/// The strategy relies on the writer to signal it, so it should be used through
+/// `EventProcessor::start()`.  A strategy created with `new()` has a private signal
+/// which nobody will ever notify.
+pub struct BlockingWait {
+    ring_size: usize,
+    signal: Arc<WaitSignal>
+}
 
-        for v in deps.iter() {
-            let cursor = v.load();
-            debug!("					cursor: {}", cursor);
+impl WaitStrategy for BlockingWait {
+    fn new(ring_size: usize) -> BlockingWait {
+        BlockingWait::with_signal(ring_size, Arc::new(WaitSignal::new()))
+    }
+
+    fn with_signal(ring_size: usize, signal: Arc<WaitSignal>) -> BlockingWait {
+        BlockingWait {
+            ring_size: ring_size,
+            signal: signal
+        }
+    }
 
-            if sequence == cursor {
-                debug!("					Same as dep cursor, abort!");
-                return None;	// at same position as a dependency. we can't move
+    fn get_ring_size(&self) -> usize {
+        self.ring_size
+    }
+
+    fn wait_for(&self, sequence: u64, deps: &Vec<&Padded64>) -> u64 {
+        self.wait_until(sequence, deps, None).unwrap()
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &Vec<&Padded64>, timeout: Duration) -> Option<u64> {
+        self.wait_until(sequence, deps, Some(Instant::now() + timeout))
+    }
+}
+
+impl BlockingWait {
+    fn wait_until(&self, sequence: u64, deps: &Vec<&Padded64>, deadline: Option<Instant>) -> Option<u64> {
+        // Fast path, avoid the lock entirely if data is already available
+        if let Some(v) = can_read(sequence, deps) {
+            return Some(v);
+        }
+
+        debug!("					Blocking for: {}", sequence);
+        let mut guard = self.signal.lock.lock().unwrap();
+        self.signal.waiters.fetch_add(1, Ordering::SeqCst);
+
+        // The waiter count is incremented before re-checking, so a writer that
+        // advances its cursor after this check is guaranteed to see us and notify
+        let available: Option<u64>;
+        loop {
+            match can_read(sequence, deps) {
+                Some(v) => {
+                    available = Some(v);
+                    break
+                },
+                None if self.signal.is_alerted() => {
+                    available = Some(sequence);
+                    break
+                },
+                None => match deadline {
+                    None => guard = self.signal.condvar.wait(guard).unwrap(),
+                    Some(d) => {
+                        let now = Instant::now();
+                        if now >= d {
+                            available = None;
+                            break
+                        }
+                        guard = self.signal.condvar.wait_timeout(guard, d - now).unwrap().0;
+                    }
+                }
             }
-            min_cursor = min(min_cursor, cursor);
-            debug!("					dep cursor: {}, ring_size: {}, sequence: {}", cursor, self.ring_size as isize, sequence);
-            debug!("					min_cursor: {}", min_cursor);
+        }
 
+        self.signal.waiters.fetch_sub(1, Ordering::SeqCst);
+        debug!("					Wait done, returning {:?}", available);
+        available
+    }
+}
+
+/// An implementation of WaitStrategy that spins for a while, then yields its timeslice
+///
+/// After `YIELD_SPIN_TRIES` unsuccessful checks the thread calls `thread::yield_now()`
+/// between every check.  This gives up the core to other threads when the ring is
+/// idle, while still reacting quickly since the thread never actually sleeps.  A good
+/// compromise if you have more EventProcessors than cores.
+pub struct YieldingWait {
+    ring_size: usize,
+    signal: Arc<WaitSignal>
+}
+
+impl WaitStrategy for YieldingWait {
+    fn new(ring_size: usize) -> YieldingWait {
+        YieldingWait::with_signal(ring_size, Arc::new(WaitSignal::new()))
+    }
+
+    fn with_signal(ring_size: usize, signal: Arc<WaitSignal>) -> YieldingWait {
+        YieldingWait {
+            ring_size: ring_size,
+            signal: signal
         }
-        Some(min_cursor)
+    }
+
+    fn get_ring_size(&self) -> usize {
+        self.ring_size
+    }
+
+    fn wait_for(&self, sequence: u64, deps: &Vec<&Padded64>) -> u64 {
+        self.wait_until(sequence, deps, None).unwrap()
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &Vec<&Padded64>, timeout: Duration) -> Option<u64> {
+        self.wait_until(sequence, deps, Some(Instant::now() + timeout))
     }
 }
 
-impl WaitStrategy for BusyWait {
-    fn new(ring_size: usize) -> BusyWait {
-        BusyWait {
-            ring_size: ring_size
+impl YieldingWait {
+    fn wait_until(&self, sequence: u64, deps: &Vec<&Padded64>, deadline: Option<Instant>) -> Option<u64> {
+        let available: Option<u64>;
+        let mut tries = 0;
+        debug!("					Waiting for: {}", sequence);
+        loop {
+            match can_read(sequence, deps) {
+                Some(v) => {
+                    available = Some(v);
+                    break
+                },
+                None if self.signal.is_alerted() => {
+                    available = Some(sequence);
+                    break
+                },
+                None if expired(&deadline) => {
+                    available = None;
+                    break
+                },
+                None if tries < YIELD_SPIN_TRIES => tries += 1,
+                None => thread::yield_now()
+            }
+        }
+        debug!("					Wait done, returning {:?}", available);
+        available
+    }
+}
+
+/// An implementation of WaitStrategy that progressively backs off to short sleeps
+///
+/// The strategy first busy-spins `SLEEP_SPIN_TRIES` times, then yields
+/// `SLEEP_YIELD_TRIES` times, and finally sleeps.  The sleep starts at
+/// `SLEEP_MIN_NS` and doubles on every unsuccessful check up to `SLEEP_MAX_NS`.
+/// CPU usage on an idle ring is very low, but the first event after a quiet period
+/// may be delayed by up to the maximum sleep.
+pub struct SleepingWait {
+    ring_size: usize,
+    signal: Arc<WaitSignal>
+}
+
+impl WaitStrategy for SleepingWait {
+    fn new(ring_size: usize) -> SleepingWait {
+        SleepingWait::with_signal(ring_size, Arc::new(WaitSignal::new()))
+    }
+
+    fn with_signal(ring_size: usize, signal: Arc<WaitSignal>) -> SleepingWait {
+        SleepingWait {
+            ring_size: ring_size,
+            signal: signal
         }
     }
 
@@ -63,18 +446,189 @@ impl WaitStrategy for BusyWait {
     }
 
     fn wait_for(&self, sequence: u64, deps: &Vec<&Padded64>) -> u64 {
-        let available: u64;
+        self.wait_until(sequence, deps, None).unwrap()
+    }
+
+    fn wait_for_timeout(&self, sequence: u64, deps: &Vec<&Padded64>, timeout: Duration) -> Option<u64> {
+        self.wait_until(sequence, deps, Some(Instant::now() + timeout))
+    }
+}
+
+impl SleepingWait {
+    fn wait_until(&self, sequence: u64, deps: &Vec<&Padded64>, deadline: Option<Instant>) -> Option<u64> {
+        let available: Option<u64>;
+        let mut tries = 0;
+        let mut sleep_ns = SLEEP_MIN_NS;
         debug!("					Waiting for: {}", sequence);
         loop {
-            match self.can_read(sequence, deps) {
+            match can_read(sequence, deps) {
                 Some(v) => {
-                    available = v;
+                    available = Some(v);
                     break
                 },
-                None => {}
+                None if self.signal.is_alerted() => {
+                    available = Some(sequence);
+                    break
+                },
+                None if expired(&deadline) => {
+                    available = None;
+                    break
+                },
+                None if tries < SLEEP_SPIN_TRIES => tries += 1,
+                None if tries < SLEEP_SPIN_TRIES + SLEEP_YIELD_TRIES => {
+                    tries += 1;
+                    thread::yield_now();
+                },
+                None => {
+                    thread::sleep(Duration::new(0, sleep_ns));
+                    sleep_ns = min(sleep_ns * 2, SLEEP_MAX_NS);
+                }
             }
         }
-        debug!("					Wait done, returning {}", available);
+        debug!("					Wait done, returning {:?}", available);
         available
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+    use paddedatomics::Padded64;
+    use futures::{future, Future, Poll, Async};
+    use super::{WaitStrategy, WaitSignal, BusyWait, BlockingWait, YieldingWait, SleepingWait};
+
+    #[test]
+    fn busy_wait_available() {
+        let cursor = Padded64::new(5);
+        let w: BusyWait = WaitStrategy::new(8);
+        assert!(w.wait_for(2, &vec![&cursor]) == 5);
+    }
+
+    #[test]
+    fn blocking_wait_notified() {
+        let signal = Arc::new(WaitSignal::new());
+        let cursor = Arc::new(Padded64::new(0));
+
+        let (s, c) = (signal.clone(), cursor.clone());
+        let _future = thread::spawn(move|| {
+            thread::sleep(Duration::from_millis(50));
+            c.store(3);
+            s.notify();
+        });
+
+        let w: BlockingWait = WaitStrategy::with_signal(8, signal);
+        assert!(w.wait_for(0, &vec![&*cursor]) == 3);
+    }
+
+    fn delayed_store<W: WaitStrategy>() {
+        let cursor = Arc::new(Padded64::new(0));
+
+        let c = cursor.clone();
+        let _future = thread::spawn(move|| {
+            thread::sleep(Duration::from_millis(20));
+            c.store(7);
+        });
+
+        let w: W = WaitStrategy::new(8);
+        assert!(w.wait_for(0, &vec![&*cursor]) == 7);
+    }
+
+    #[test]
+    fn yielding_wait_delayed() {
+        delayed_store::<YieldingWait>();
+    }
+
+    #[test]
+    fn sleeping_wait_delayed() {
+        delayed_store::<SleepingWait>();
+    }
+
+    #[test]
+    fn wait_for_timeout_expires() {
+        let cursor = Padded64::new(0);
+        let deps = vec![&cursor];
+        let timeout = Duration::from_millis(10);
+
+        let w: BusyWait = WaitStrategy::new(8);
+        assert!(w.wait_for_timeout(0, &deps, timeout).is_none());
+        let w: BlockingWait = WaitStrategy::new(8);
+        assert!(w.wait_for_timeout(0, &deps, timeout).is_none());
+        let w: YieldingWait = WaitStrategy::new(8);
+        assert!(w.wait_for_timeout(0, &deps, timeout).is_none());
+        let w: SleepingWait = WaitStrategy::new(8);
+        assert!(w.wait_for_timeout(0, &deps, timeout).is_none());
+
+        cursor.store(2);
+        assert!(w.wait_for_timeout(0, &deps, timeout) == Some(2));
+    }
+
+    /// A strategy which only implements the required methods
+    struct Minimal {
+        ring_size: usize
+    }
+
+    impl WaitStrategy for Minimal {
+        fn new(ring_size: usize) -> Minimal {
+            Minimal { ring_size: ring_size }
+        }
+
+        fn get_ring_size(&self) -> usize {
+            self.ring_size
+        }
+
+        fn wait_for(&self, sequence: u64, deps: &Vec<&Padded64>) -> u64 {
+            self.wait_for_timeout(sequence, deps, Duration::from_secs(3600)).unwrap()
+        }
+    }
+
+    #[test]
+    fn default_wait_for_timeout() {
+        let cursor = Padded64::new(0);
+        let deps = vec![&cursor];
+
+        let w: Minimal = WaitStrategy::with_signal(8, Arc::new(WaitSignal::new()));
+        assert!(w.wait_for_timeout(0, &deps, Duration::from_millis(10)).is_none());
+
+        cursor.store(4);
+        assert!(w.wait_for(0, &deps) == 4);
+    }
+
+    #[test]
+    fn alerted_returns_sequence() {
+        let signal = Arc::new(WaitSignal::new());
+        let cursor = Padded64::new(3);
+        let deps = vec![&cursor];
+        signal.alert();
+
+        let w: BusyWait = WaitStrategy::with_signal(8, signal.clone());
+        assert!(w.wait_for(3, &deps) == 3);
+        let w: BlockingWait = WaitStrategy::with_signal(8, signal.clone());
+        assert!(w.wait_for(3, &deps) == 3);
+        let w: YieldingWait = WaitStrategy::with_signal(8, signal.clone());
+        assert!(w.wait_for(3, &deps) == 3);
+        let w: SleepingWait = WaitStrategy::with_signal(8, signal.clone());
+        assert!(w.wait_for(3, &deps) == 3);
+
+        // Data which is still available is returned, so EPs can drain
+        assert!(w.wait_for(1, &deps) == 3);
+    }
+
+    #[test]
+    fn park_once_per_task() {
+        let signal = WaitSignal::new();
+        future::poll_fn(|| -> Poll<(), ()> {
+            signal.park();
+            signal.park();
+            assert!(signal.parked.load(Ordering::SeqCst) == 1);
+            Ok(Async::Ready(()))
+        }).wait().unwrap();
+
+        signal.notify();
+        assert!(signal.parked.load(Ordering::SeqCst) == 0);
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::cmp::min;
+use std::thread;
+use waitstrategy::{WaitStrategy, WaitSignal};
+use paddedatomics::Padded64;
+use ringbuffer::{RingBuffer, Slot};
+use sequencer::{self, AvailableBuffer};
+use handler::EventHandler;
+use latency::Latency;
+use exception;
+
+/// Worker cursors are set to this once the worker has stopped, so it no longer holds
+/// back the pool's cursor
+const STOPPED: u64 = 18446744073709551615;
+
+/// State shared by all the workers of a pool
+struct PoolState {
+    work: Padded64,
+    workers: Vec<Padded64>,
+    running: AtomicUsize
+}
+
+/// A group of workers which share the events seen by a single node of the graph
+///
+/// Each EventProcessor sees every event.  The workers of a pool instead compete for
+/// sequences through a shared work cursor, so each event is handled by exactly one
+/// worker.  This spreads CPU-heavy stages over several threads.
+///
+/// To the rest of the graph the pool is a single node: other EPs may `ep_depends()`
+/// on the pool's token, and will only see an event once the worker handling it (and
+/// every worker handling an earlier event) has finished.  Since events are handed out
+/// one at a time, workers do not batch and may finish events out of order.
+///
+/// Obtained from `Turbine::pool_finalize()`.  Every worker must be started, otherwise
+/// the pool's cursor never moves past the first event left to the idle worker.
+pub struct WorkerPool<T> {
+    workers: Vec<Worker<T>>
+}
+
+impl<T: Slot> WorkerPool<T> {
+
+    /// Instantiate a new WorkerPool.
+    ///
+    /// This is for internal use only, see `EventProcessor::new()` for the parameters.
+    /// `size` is the number of workers in the pool.
+    pub fn new(ring: Arc<RingBuffer<T>>, graph: Arc<Vec<Vec<usize>>>, cursors: Arc<Vec<Padded64>>,
+               stopped: Arc<Vec<AtomicBool>>, available: Option<Arc<AvailableBuffer>>,
+               signal: Arc<WaitSignal>, token: usize, size: usize,
+               latency: Option<Arc<Latency>>) -> WorkerPool<T> {
+        let state = Arc::new(PoolState {
+            work: Padded64::new(0),
+            workers: (0..size).map(|_| Padded64::new(0)).collect(),
+            running: AtomicUsize::new(size)
+        });
+
+        WorkerPool::<T> {
+            workers: (0..size).map(|index| Worker::<T> {
+                graph: graph.clone(),
+                cursors: cursors.clone(),
+                stopped: stopped.clone(),
+                token: token,
+                index: index,
+                ring: ring.clone(),
+                available: available.clone(),
+                signal: signal.clone(),
+                latency: latency.clone(),
+                state: state.clone()
+            }).collect()
+        }
+    }
+
+    /// The number of workers in the pool
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Split the pool into its workers, so they can be moved into separate threads
+    pub fn workers(self) -> Vec<Worker<T>> {
+        self.workers
+    }
+}
+
+/// A single worker of a WorkerPool
+pub struct Worker<T> {
+    graph: Arc<Vec<Vec<usize>>>,
+    cursors: Arc<Vec<Padded64>>,
+    stopped: Arc<Vec<AtomicBool>>,
+    token: usize,
+    index: usize,
+    ring: Arc<RingBuffer<T>>,
+    available: Option<Arc<AvailableBuffer>>,
+    signal: Arc<WaitSignal>,
+    latency: Option<Arc<Latency>>,
+    state: Arc<PoolState>
+}
+
+impl<T: Slot> Worker<T> {
+
+    /// Begin competing for events with the other workers of the pool.
+    ///
+    /// The closure is called with each event this worker claims, and the event's
+    /// sequence.  Like `EventProcessor::start()`, returning Err(e) stops the worker and
+    /// `start()` returns that same Err(e); the other workers carry on.  After a halt,
+    /// the worker returns Ok with the sequence it was waiting for.  A panic in the
+    /// closure is handled like in `EventProcessor::start()`.
+    ///
+    /// ## Example
+    ///
+    ///```
+    ///for worker in pool.workers() {
+    ///    thread::spawn(move|| {
+    ///        worker.start::<_, _, BusyWait>(|event: &TestSlot, sequence: u64| -> Result<(), ()> {
+    ///            // ... expensive work here ... //
+    ///            Ok(())
+    ///        });
+    ///    });
+    ///}
+    ///```
+    pub fn start<F, E, W: WaitStrategy>(&self, f: F) -> Result<u64, E>
+    where F: FnMut(&T, u64) -> Result<(), E> {
+        self.run::<_, E, W>(f)
+    }
+
+    /// Begin competing for events, passing each claimed event to an EventHandler.
+    ///
+    /// See `EventProcessor::start_with_handler()`.  Workers handle one event at a time,
+    /// so `end_of_batch` is always true.
+    pub fn start_with_handler<H, W: WaitStrategy>(&self, handler: &mut H) -> Result<u64, H::Error>
+    where H: EventHandler<T> + ?Sized {
+        handler.on_start();
+        let result = self.run::<_, H::Error, W>(|event: &T, sequence: u64| handler.on_event(event, sequence, true));
+        handler.on_shutdown();
+        result
+    }
+
+    /// Recompute the pool's cursor from the worker cursors
+    ///
+    /// Each worker cursor holds the sequence that worker is waiting for (or processing),
+    /// and sequences are claimed in order, so everything below the minimum has been
+    /// handled.  Workers may race here, so the pool cursor only ever moves forward.
+    fn advance_pool(&self) {
+        let mut lowest = STOPPED;
+        for cursor in self.state.workers.iter() {
+            lowest = min(lowest, cursor.load());
+        }
+        if lowest == STOPPED {
+            return;
+        }
+
+        let ref pool = (*self.cursors).as_slice()[self.token + 1];
+        let mut current = pool.load();
+        while current < lowest {
+            let previous = pool.compare_and_swap(current, lowest);
+            if previous == current {
+                break;
+            }
+            current = previous;
+        }
+        self.signal.notify();   // wake any blocked EPs which depend on the pool
+    }
+
+    /// Returns true if every dependency of the pool has stopped, see `sequencer::dependencies_stopped()`
+    fn dependencies_stopped(&self) -> bool {
+        sequencer::dependencies_stopped(&self.graph.as_slice()[self.token], self.stopped.as_slice())
+    }
+
+    fn run<F, E, W: WaitStrategy>(&self, mut f: F) -> Result<u64, E>
+    where F: FnMut(&T, u64) -> Result<(), E> {
+        let capacity = self.ring.get_capacity();
+        let wait_strategy: W = WaitStrategy::with_signal(capacity, self.signal.clone());
+
+        let mut deps: Vec<&Padded64> = Vec::with_capacity(self.graph.as_slice()[self.token].len());
+        for ep in self.graph.as_slice()[self.token].iter() {
+            deps.push(&(*self.cursors).as_slice()[*ep]);
+        }
+
+        let ref cursor = self.state.workers[self.index];
+        let mask: u64 = capacity as u64 - 1;
+        let mut error: Option<E> = None;
+
+        let mut sequence = self.state.work.add(1);
+        cursor.store(sequence);
+        self.advance_pool();
+
+        loop {
+            debug!("              Worker {} waiting on: {}", self.index, sequence);
+
+            // See EventProcessor::run(), this must be checked before waiting
+            let drained = self.signal.is_alerted() && self.dependencies_stopped();
+
+            let mut available = wait_strategy.wait_for(sequence, &deps);
+            let claimed = available;
+            if let Some(ref published) = self.available {
+                available = published.highest_published(sequence, available);
+            }
+
+            if available <= sequence {
+                if drained {
+                    break;
+                }
+
+                // See EventProcessor::run(), don't spin on a claimed but unpublished slot
+                if claimed > sequence {
+                    thread::yield_now();
+                }
+                continue;
+            }
+
+            // This is safe because the Producer cannot pass the pool's cursor, which
+            // stays at or below our claimed sequence until we claim the next one
+            let offset = (sequence & mask) as usize;
+            let status = exception::guard(|| f(unsafe { &self.ring.get(offset, 1)[0] }, sequence),
+                                          &mut None, &self.signal, &self.ring, offset, sequence);
+
+            if let Some(ref latency) = self.latency {
+                latency.record(self.token, sequence, sequence + 1);
+            }
+
+            if let Err(e) = status {
+                error = Some(e);
+                break;
+            }
+
+            sequence = self.state.work.add(1);
+            cursor.store(sequence);
+            self.advance_pool();
+        }
+
+        cursor.store(STOPPED);
+        self.advance_pool();
+
+        // The last worker out stops the pool, so EPs behind it can finish draining
+        if self.state.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.stopped.as_slice()[self.token + 1].store(true, Ordering::SeqCst);
+            self.signal.notify();
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(sequence)
+        }
+    }
+}